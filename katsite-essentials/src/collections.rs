@@ -0,0 +1,349 @@
+//! Named collections of pages, like a blog's posts, set up in the config file's `[collections]` table.
+//!
+//! A collection's pages are sorted by the `date` field in their frontmatter, newest first, and are available to templates as `site.collections.<name>`. Each collection can have paginated list pages and year/month archive pages, which are rendered from its own templates and placed into the site's layout.
+
+use crate::{url, Config, Failure, Generated, Page};
+use glob::Pattern;
+use htmlescape::encode_attribute;
+use serde_derive::{Serialize, Deserialize};
+use std::{cmp::Ordering, collections::BTreeMap, iter, path::{Path, PathBuf}};
+
+#[derive(Deserialize)]
+pub struct Settings {
+	glob: String,
+	title: Option<String>,
+	path: Option<String>,
+	#[serde(default = "default_per_page")]
+	per_page: usize,
+	#[serde(default)]
+	oldest_first: bool,
+	pub list_template: Option<PathBuf>,
+	pub archive_template: Option<PathBuf>,
+}
+
+const fn default_per_page() -> usize {
+	10
+}
+
+impl Settings {
+	// The directory that the collection's generated pages are placed in.
+	fn dir(&self, name: &str) -> String {
+		self.path.as_deref().unwrap_or(name).trim_matches('/').to_string()
+	}
+}
+
+#[derive(Serialize, Clone)]
+pub struct Collection {
+	name: String,
+	title: String,
+	// The collection's first list page, if it has a list template.
+	path_url: Option<String>,
+	path_raw: Option<String>,
+	pages: Vec<Page>,
+	archives: Vec<ArchiveYear>,
+}
+
+#[derive(Serialize, Clone)]
+struct ArchiveYear {
+	year: String,
+	count: usize,
+	path_url: Option<String>,
+	months: Vec<ArchiveMonth>,
+}
+
+#[derive(Serialize, Clone)]
+struct ArchiveMonth {
+	month: String,
+	count: usize,
+	path_url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Paginator<'a> {
+	number: usize,
+	total: usize,
+	pages: &'a [Page],
+	previous: Option<String>,
+	next: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Archive<'a> {
+	year: &'a str,
+	month: Option<&'a str>,
+	pages: Vec<&'a Page>,
+}
+
+fn list_path(dir: &str, number: usize) -> String {
+	let file = if number == 1 {
+		"index.html".to_string()
+	} else {
+		["page/", &number.to_string(), ".html"].concat()
+	};
+	if dir.is_empty() {
+		file
+	} else {
+		[dir, "/", &file].concat()
+	}
+}
+
+fn archive_path(dir: &str, year: &str, month: Option<&str>) -> String {
+	let mut segments: Vec<&str> = vec![dir, year];
+	segments.extend(month);
+	segments.push("index.html");
+	segments.retain(|segment| !segment.is_empty());
+	segments.join("/")
+}
+
+/// Parses every collection's glob.
+pub fn patterns<'a>(config: &'a Config, failures: &mut Vec<Failure>) -> Vec<(&'a str, Pattern)> {
+	config.collections.iter().filter_map(|(name, settings)| {
+		match Pattern::new(settings.glob.trim_start_matches("./")) {
+			Ok(pattern) => Some((name.as_str(), pattern)),
+			Err(err) => {
+				failures.push(Failure::new(&settings.glob, format!("Unable to create file glob! {}", err)));
+				None
+			},
+		}
+	}).collect()
+}
+
+/// The collections that an input file belongs to.
+pub fn matching(patterns: &[(&str, Pattern)], file: &Path) -> Vec<String> {
+	patterns.iter().filter(|(_, pattern)| pattern.matches_path(file)).map(|(name, _)| name.to_string()).collect()
+}
+
+/// Sorts pages by date, newest first unless `oldest_first` is set. Undated pages are placed after dated ones, and pages with the same date are sorted by title.
+pub fn sort(pages: &mut [Page], oldest_first: bool) {
+	pages.sort_by(|a, b| {
+		match (&a.date, &b.date) {
+			(Some(a), Some(b)) if oldest_first => a.cmp(b),
+			(Some(a), Some(b)) => b.cmp(a),
+			(Some(_), None) => Ordering::Less,
+			(None, Some(_)) => Ordering::Greater,
+			(None, None) => Ordering::Equal,
+		}.then_with(|| a.title.cmp(&b.title))
+	});
+}
+
+/// Collects and sorts the pages in every collection.
+pub fn load(config: &Config, pages: &[Page]) -> BTreeMap<String, Collection> {
+	config.collections.iter().map(|(name, settings)| {
+		let mut pages: Vec<Page> = pages.iter().filter(|page| page.collections.contains(name)).cloned().collect();
+		sort(&mut pages, settings.oldest_first);
+
+		let dir = settings.dir(name);
+		let archive_url = |year: &str, month: Option<&str>| settings.archive_template.as_ref().map(|_| url(&archive_path(&dir, year, month)));
+		let mut archives: Vec<ArchiveYear> = Vec::new();
+		for date in pages.iter().filter_map(|page| page.date.as_deref()) {
+			let (year, month) = (&date[..4], &date[5..7]);
+			if archives.last().is_none_or(|last| last.year != year) {
+				archives.push(ArchiveYear {
+					year: year.to_string(),
+					count: 0,
+					path_url: archive_url(year, None),
+					months: Vec::new(),
+				});
+			}
+			let last = archives.last_mut().unwrap();
+			last.count += 1;
+			if last.months.last().is_none_or(|last| last.month != month) {
+				last.months.push(ArchiveMonth {
+					month: month.to_string(),
+					count: 0,
+					path_url: archive_url(year, Some(month)),
+				});
+			}
+			last.months.last_mut().unwrap().count += 1;
+		}
+
+		let path_raw = settings.list_template.as_ref().map(|_| list_path(&dir, 1));
+		(name.to_owned(), Collection {
+			name: name.to_owned(),
+			title: encode_attribute(settings.title.as_deref().unwrap_or(name)),
+			path_url: path_raw.as_deref().map(url),
+			path_raw,
+			pages,
+			archives,
+		})
+	}).collect()
+}
+
+/// Every list and archive page for a collection.
+pub fn generate(config: &Config, collection: &Collection) -> Vec<Generated> {
+	let settings = &config.collections[&collection.name];
+	let dir = settings.dir(&collection.name);
+	let mut generated = Vec::new();
+
+	if let Some(template) = &settings.list_template {
+		let chunks: Vec<&[Page]> = if collection.pages.is_empty() {
+			vec![&[]]
+		} else if settings.per_page == 0 {
+			vec![&collection.pages]
+		} else {
+			collection.pages.chunks(settings.per_page).collect()
+		};
+		let total = chunks.len();
+		for (index, pages) in chunks.into_iter().enumerate() {
+			let number = index + 1;
+			let paginator = Paginator {
+				number,
+				total,
+				pages,
+				previous: Some(number - 1).filter(|previous| *previous > 0).map(|previous| url(&list_path(&dir, previous))),
+				next: Some(number + 1).filter(|next| *next <= total).map(|next| url(&list_path(&dir, next))),
+			};
+			generated.push(Generated {
+				page: Page::generated(config, &list_path(&dir, number), &collection.title),
+				template: template.to_owned(),
+				globals: liquid::object!({
+					"collection": collection,
+					"paginator": paginator,
+				}),
+				contents: Generated::contents(pages),
+			});
+		}
+	}
+
+	if let Some(template) = &settings.archive_template {
+		for year in &collection.archives {
+			let months = year.months.iter().map(|month| Some(month.month.as_str()));
+			for month in iter::once(None).chain(months) {
+				let pages: Vec<&Page> = collection.pages.iter().filter(|page| {
+					page.date.as_deref().is_some_and(|date| date[..4] == year.year && month.is_none_or(|month| date[5..7] == *month))
+				}).collect();
+				let title = [&collection.title, " from ", &year.year, &month.map(|month| ["-", month].concat()).unwrap_or_default()].concat();
+				generated.push(Generated {
+					page: Page::generated(config, &archive_path(&dir, &year.year, month), &title),
+					template: template.to_owned(),
+					globals: liquid::object!({
+						"collection": collection,
+						"archive": Archive {
+							year: &year.year,
+							month,
+							pages: pages.to_owned(),
+						},
+					}),
+					contents: Generated::contents(pages),
+				});
+			}
+		}
+	}
+	generated
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use liquid::ValueView;
+
+	fn config(collection: &str) -> Config {
+		Config::from_toml(&[r#"
+			[files]
+			input_glob = "./**/*.md"
+			output_dir = "./output"
+
+			[katsite_essentials]
+			name = "Test"
+			url_stub = "https://example.com"
+			default_lang = "en"
+			default_og_type = "website"
+			default_is_nsfw = false
+			default_allow_robots = true
+			layout = "layout.liquid"
+			liquid_glob = "./*.liquid"
+			stylesheet = "style.scss"
+			favicon = "icon.png"
+			sanitizer = true
+			minifier = false
+			brotli = false
+
+			[collections.posts]
+			glob = "./posts/*.md"
+			list_template = "list.liquid"
+			archive_template = "archive.liquid"
+		"#, collection].concat()).unwrap()
+	}
+
+	fn page(config: &Config, title: &str, date: Option<&str>) -> Page {
+		let mut page = Page::generated(config, &["posts/", title, ".html"].concat(), title);
+		page.date = date.map(str::to_string);
+		page.data = title.to_string();
+		page.collections = vec!["posts".to_string()];
+		page
+	}
+
+	fn paginator_link(generated: &Generated, link: &str) -> Option<String> {
+		let paginator = generated.globals.get("paginator").unwrap().as_object().unwrap();
+		paginator.get(link).filter(|link| !link.is_nil()).map(|link| link.to_kstr().to_string())
+	}
+
+	#[test]
+	fn list_pages_are_numbered_after_the_first() {
+		assert_eq!(list_path("posts", 1), "posts/index.html");
+		assert_eq!(list_path("posts", 2), "posts/page/2.html");
+		assert_eq!(list_path("", 1), "index.html");
+		assert_eq!(list_path("", 3), "page/3.html");
+	}
+
+	#[test]
+	fn archives_are_placed_by_year_and_month() {
+		assert_eq!(archive_path("posts", "2024", None), "posts/2024/index.html");
+		assert_eq!(archive_path("posts", "2024", Some("01")), "posts/2024/01/index.html");
+		assert_eq!(archive_path("", "2024", Some("01")), "2024/01/index.html");
+	}
+
+	#[test]
+	fn pages_are_sorted_by_date_then_title() {
+		let config = config("");
+		let mut pages = vec![page(&config, "b", None), page(&config, "old", Some("2023-05-01")), page(&config, "a", None), page(&config, "new", Some("2024-01-31T09:00:00Z"))];
+		sort(&mut pages, false);
+		let titles: Vec<&str> = pages.iter().map(|page| page.title.as_str()).collect();
+		assert_eq!(titles, ["new", "old", "a", "b"]);
+		sort(&mut pages, true);
+		let titles: Vec<&str> = pages.iter().map(|page| page.title.as_str()).collect();
+		assert_eq!(titles, ["old", "new", "a", "b"]);
+	}
+
+	#[test]
+	fn archives_group_pages_by_year_and_month() {
+		let config = config("");
+		let pages = [
+			page(&config, "a", Some("2024-02-10")),
+			page(&config, "b", Some("2023-12-24")),
+			page(&config, "c", Some("2024-02-01T09:00:00Z")),
+			page(&config, "d", Some("2024-01-31")),
+			page(&config, "undated", None),
+		];
+		let collections = load(&config, &pages);
+		let archives = &collections["posts"].archives;
+		let years: Vec<(&str, usize)> = archives.iter().map(|year| (year.year.as_str(), year.count)).collect();
+		assert_eq!(years, [("2024", 3), ("2023", 1)]);
+		let months: Vec<(&str, &str, usize)> = archives.iter().flat_map(|year| year.months.iter().map(move |month| (year.year.as_str(), month.month.as_str(), month.count))).collect();
+		assert_eq!(months, [("2024", "02", 2), ("2024", "01", 1), ("2023", "12", 1)]);
+		assert_eq!(archives[0].months[1].path_url.as_deref(), Some("posts/2024/01/index.html"));
+	}
+
+	#[test]
+	fn list_pages_are_paginated() {
+		let config = config("per_page = 2\npath = \"blog\"\n");
+		let pages: Vec<Page> = ["a", "b", "c", "d", "e"].iter().map(|title| page(&config, title, None)).collect();
+		let collections = load(&config, &pages);
+		let generated = generate(&config, &collections["posts"]);
+		let lists: Vec<(&str, &str)> = generated.iter().map(|generated| (generated.page.path_raw.as_str(), generated.contents.as_str())).collect();
+		assert_eq!(lists, [("blog/index.html", "ab"), ("blog/page/2.html", "cd"), ("blog/page/3.html", "e")]);
+		assert_eq!(paginator_link(&generated[0], "previous"), None);
+		assert_eq!(paginator_link(&generated[0], "next").as_deref(), Some("blog/page/2.html"));
+		assert_eq!(paginator_link(&generated[1], "previous").as_deref(), Some("blog/index.html"));
+		assert_eq!(paginator_link(&generated[2], "next"), None);
+	}
+
+	#[test]
+	fn empty_collections_still_have_a_list_page() {
+		let config = config("per_page = 0\n");
+		let collections = load(&config, &[]);
+		let generated = generate(&config, &collections["posts"]);
+		let paths: Vec<&str> = generated.iter().map(|generated| generated.page.path_raw.as_str()).collect();
+		assert_eq!(paths, ["posts/index.html"]);
+	}
+}
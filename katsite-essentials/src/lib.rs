@@ -0,0 +1,1225 @@
+#![warn(clippy::nursery)]
+#![warn(clippy::pedantic)]
+#![allow(clippy::struct_excessive_bools)]
+#![allow(clippy::cast_possible_truncation)]
+#![warn(clippy::cargo)]
+#![allow(clippy::multiple_crate_versions)]
+#![allow(clippy::cargo_common_metadata)]
+#![allow(clippy::uninlined_format_args)]
+#![allow(clippy::unnecessary_debug_formatting)]
+#![allow(clippy::implicit_clone)]
+#![allow(clippy::manual_let_else)]
+#![allow(clippy::option_if_let_else)]
+#![allow(clippy::map_unwrap_or)]
+#![allow(clippy::too_many_lines)]
+#![warn(clippy::all)]
+
+//! Essential plugins for the site generator: page templates, stylesheets, favicons, minification and compression.
+//!
+//! These can be run in-process by the site generator, or as the `katsite-essentials` plugin program.
+
+use ammonia::Builder;
+use brotli::enc::{writer::CompressorWriter, backward_references::{BrotliEncoderParams, BrotliHasherParams, BrotliEncoderMode}, command::BrotliDistanceParams, encode::{BROTLI_MAX_DISTANCE, BROTLI_MAX_DISTANCE_BITS, BROTLI_DISTANCE_ALPHABET_SIZE}};
+use glob::glob;
+use htmlescape::encode_attribute;
+#[allow(deprecated)]
+use image::{imageops::FilterType::Lanczos3, math::nq::NeuQuant, imageops::colorops::ColorMap};
+use liquid::ParserBuilder;
+use minify_html::{Cfg, truncate};
+use oxipng::{optimize, InFile, OutFile, Options, Headers::All, Deflaters::Zopfli};
+use rayon::prelude::*;
+use sass_rs::{compile_file, OutputStyle::Expanded};
+use seahash::SeaHasher;
+use serde::{de::DeserializeOwned, ser::Serialize};
+use serde_derive::{Serialize, Deserialize};
+use std::{fmt, fs, collections::{BTreeMap, HashMap, HashSet}, hash::Hasher, fs::File, ffi::OsStr, time::{Duration, UNIX_EPOCH}, io::{Read, Write}, process::{Command, Stdio}, path::{Component, Path, PathBuf}, sync::atomic::{AtomicU8, Ordering}, thread};
+use urlencoding::encode;
+
+mod collections;
+mod taxonomies;
+
+/// The parts of the site's config file used by the plugins.
+#[derive(Deserialize)]
+pub struct Config {
+	files: Files,
+	#[serde(default)]
+	markdown: Markdown,
+	katsite_essentials: Plugin,
+	#[serde(default)]
+	collections: BTreeMap<String, collections::Settings>,
+	#[serde(default)]
+	taxonomies: taxonomies::Taxonomies,
+	#[serde(skip)]
+	raw: String,
+}
+
+#[derive(Deserialize)]
+struct Files {
+	input_glob: String,
+	output_dir: PathBuf,
+	#[serde(default = "default_incremental")]
+	incremental: bool,
+}
+
+const fn default_incremental() -> bool {
+	true
+}
+
+// Every MathML element that the site generator renders math to.
+const MATHML_TAGS: &[&str] = &["math", "semantics", "annotation", "mrow", "mi", "mn", "mo", "mtext", "mspace", "msub", "msup", "msubsup", "munder", "mover", "munderover", "mfrac", "msqrt", "mroot", "mtable", "mtr", "mtd"];
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Markdown {
+	github_extensions: bool,
+	header_ids: Option<bool>,
+	heading_anchors: bool,
+	toc: bool,
+	highlight: String,
+	highlight_stylesheet: PathBuf,
+	math: bool,
+}
+
+impl Default for Markdown {
+	fn default() -> Self {
+		Self {
+			github_extensions: false,
+			header_ids: None,
+			heading_anchors: false,
+			toc: false,
+			highlight: "none".to_string(),
+			highlight_stylesheet: PathBuf::from("highlight.css"),
+			math: false,
+		}
+	}
+}
+
+impl Markdown {
+	// Keeps header ids and self-links, the attributes that highlighted code blocks are styled with, and the MathML that math is rendered to.
+	fn sanitizer(&self) -> Builder<'static> {
+		let mut builder = Builder::default();
+		if self.toc || self.header_ids.unwrap_or(self.github_extensions) {
+			for header in &["h1", "h2", "h3", "h4", "h5", "h6"] {
+				builder.add_tag_attributes(header, &["id"]);
+			}
+		}
+		if self.heading_anchors {
+			builder.add_tag_attributes("a", &["class", "aria-hidden"]);
+		}
+		match self.highlight.as_str() {
+			"classes" => {
+				builder.add_tag_attributes("pre", &["class"])
+					.add_tag_attributes("code", &["class"])
+					.add_tag_attributes("span", &["class"]);
+			},
+			"inline" => {
+				builder.add_tag_attributes("pre", &["style"])
+					.add_tag_attributes("code", &["class"])
+					.add_tag_attributes("span", &["style"]);
+			},
+			_ => {},
+		}
+		if self.math {
+			builder.add_tags(MATHML_TAGS)
+				.add_tag_attributes("math", &["display"])
+				.add_tag_attributes("mi", &["mathvariant"])
+				.add_tag_attributes("mo", &["fence", "stretchy", "minsize", "maxsize", "lspace", "rspace"])
+				.add_tag_attributes("mtext", &["mathvariant"])
+				.add_tag_attributes("mspace", &["width"])
+				.add_tag_attributes("mfrac", &["linethickness"])
+				.add_tag_attributes("mover", &["accent"])
+				.add_tag_attributes("munder", &["accentunder"])
+				.add_tag_attributes("mtable", &["columnalign", "columnspacing"])
+				.add_tag_attributes("annotation", &["encoding"]);
+		}
+		builder
+	}
+
+	// The stylesheet generated by the site generator for highlighting code blocks with classes, which is merged into the site's stylesheet.
+	fn highlight_stylesheet(&self, output_dir: &Path) -> Option<PathBuf> {
+		if self.highlight == "classes" {
+			Some(output_dir.join(&self.highlight_stylesheet))
+		} else {
+			None
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct Plugin {
+	name: String,
+	url_stub: String,
+
+	default_lang: String,
+	default_og_type: String,
+	default_is_nsfw: bool,
+	default_allow_robots: bool,
+
+	layout: PathBuf,
+	liquid_glob: String,
+	stylesheet: PathBuf,
+	favicon: PathBuf,
+
+	sanitizer: bool,
+	minifier: bool,
+	brotli: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct FrontMatter {
+	title: Option<String>,
+	description: Option<String>,
+	locale: Option<String>,
+	is_nsfw: Option<bool>,
+	allow_robots: Option<bool>,
+	og_type: Option<String>,
+	og_image: Option<String>,
+	og_audio: Option<String>,
+	og_video: Option<String>,
+	date: Option<toml::Value>,
+	tags: Option<Vec<String>>,
+	categories: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Page {
+	#[serde(skip)]
+	cached: bool,
+	created_time: u64,
+	modified_time: u64,
+	filename: String,
+	filename_url: String,
+	filename_raw: String,
+	path: String,
+	path_url: String,
+	path_raw: String,
+	dir: String,
+	root: String,
+	data: String,
+	title: String,
+	description: Option<String>,
+	locale: String,
+	is_nsfw: bool,
+	allow_robots: bool,
+	og_type: String,
+	og_image: Option<String>,
+	og_audio: Option<String>,
+	og_video: Option<String>,
+	// Written as in TOML, like `2024-01-31` or `2024-01-31T09:00:00Z`, so that dates sort correctly as strings.
+	date: Option<String>,
+	collections: Vec<String>,
+	toc: Vec<Heading>,
+	tags: Vec<taxonomies::Term>,
+	categories: Vec<taxonomies::Term>,
+}
+
+// A header in a page's table of contents, which the site generator adds to the end of the page. Its text and id are already escaped.
+#[derive(Serialize, Deserialize, Clone)]
+struct Heading {
+	level: u8,
+	text: String,
+	id: String,
+	children: Vec<Self>,
+}
+
+#[derive(Deserialize)]
+struct Toc {
+	toc: Vec<Heading>,
+}
+
+const TOC_START: &str = "<!--toc\n";
+
+// Starts the comment holding a page's frontmatter, which the site generator adds to the end of the page as TOML.
+const FRONTMATTER_START: &str = "<!--frontmatter\n";
+
+#[derive(Serialize)]
+struct Site {
+	name: String,
+	url_stub: String,
+	pages: Vec<Page>,
+	collections: BTreeMap<String, collections::Collection>,
+	taxonomies: BTreeMap<String, taxonomies::Taxonomy>,
+	// The list, archive and term pages, without their contents.
+	generated: Vec<Page>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct AssetCache {
+	stylesheet: Option<String>,
+	favicon: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PageCache {
+	templates: HashMap<String, String>,
+	pages: HashMap<String, CachedPage>,
+	// Hashes of the pages generated for collections and taxonomies, by path.
+	#[serde(default)]
+	generated: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedPage {
+	output: String,
+	render: String,
+	page: Page,
+}
+
+/// A file that couldn't be built. The rest of the site is still built, and every failure is reported at the end.
+#[derive(Debug)]
+pub struct Failure {
+	pub file: String,
+	pub cause: String,
+}
+
+impl Failure {
+	fn new<P: AsRef<Path>, E: fmt::Display>(file: P, cause: E) -> Self {
+		Self {
+			file: file.as_ref().to_string_lossy().to_string(),
+			cause: cause.to_string(),
+		}
+	}
+}
+
+impl fmt::Display for Failure {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {}", self.file, self.cause)
+	}
+}
+
+const ASSET_CACHE: &str = "katsite-essentials-assets.toml";
+const PAGE_CACHE: &str = "katsite-essentials-pages.toml";
+
+impl Config {
+	/// Parses the config file's contents.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the input isn't valid TOML, or is missing required settings.
+	pub fn from_toml(input: &str) -> Result<Self, toml::de::Error> {
+		let mut config: Self = toml::from_str(input)?;
+		input.clone_into(&mut config.raw);
+		Ok(config)
+	}
+
+	// The templates for collections' and taxonomies' pages.
+	fn generated_templates(&self) -> Vec<&PathBuf> {
+		let collections = self.collections.values().flat_map(|settings| settings.list_template.iter().chain(&settings.archive_template));
+		let taxonomies = self.taxonomies.all().into_iter().flat_map(|(_, _, settings)| settings.index_template.iter().chain(&settings.term_template));
+		collections.chain(taxonomies).collect()
+	}
+}
+
+const QUIET: u8 = 0;
+const NORMAL: u8 = 1;
+const VERBOSE: u8 = 2;
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(VERBOSE);
+
+/// Sets how much progress output to print (`quiet`, `normal` or `verbose`). Defaults to `verbose`.
+pub fn set_verbosity(verbosity: &str) {
+	let level = match verbosity {
+		"quiet" => QUIET,
+		"normal" => NORMAL,
+		_ => VERBOSE,
+	};
+	VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+fn verbose() -> bool {
+	VERBOSITY.load(Ordering::Relaxed) == VERBOSE
+}
+
+macro_rules! progress {
+	($($arg:tt)*) => {
+		if VERBOSITY.load(Ordering::Relaxed) != QUIET {
+			println!($($arg)*);
+		}
+	};
+}
+
+fn load_cache<T: DeserializeOwned + Default>(config: &Config, name: &str) -> T {
+	if !config.files.incremental {
+		return T::default()
+	}
+	fs::read_to_string(config.files.output_dir.join(".katsite-cache").join(name)).ok()
+		.and_then(|cache| toml::from_str(&cache).ok())
+		.unwrap_or_default()
+}
+
+// Serializes through `toml::Value`, which writes plain values before tables as TOML requires, even when a page's empty arrays come after its tables.
+fn to_toml<T: Serialize>(value: &T) -> Result<String, toml::ser::Error> {
+	toml::Value::try_from(value).and_then(|value| toml::to_string(&value))
+}
+
+fn save_cache<T: Serialize>(config: &Config, name: &str, cache: &T) -> Result<(), Failure> {
+	if !config.files.incremental {
+		return Ok(())
+	}
+	let path = config.files.output_dir.join(".katsite-cache").join(name);
+	let output = to_toml(cache).map_err(|err| Failure::new(&path, format!("Unable to serialize build cache! {}", err)))?;
+	if let Some(parent) = path.parent() {
+		let _ = fs::create_dir_all(parent);
+	}
+	fs::write(&path, output).map_err(|err| Failure::new(&path, format!("Unable to write build cache! {}", err)))
+}
+
+fn hash_parts<T: AsRef<[u8]>>(parts: &[T]) -> String {
+	let mut hasher = SeaHasher::new();
+	hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+	for part in parts {
+		hasher.write_usize(part.as_ref().len());
+		hasher.write(part.as_ref());
+	}
+	format!("{:016x}", hasher.finish())
+}
+
+fn stylesheet_sources(path: &Path, sources: &mut Vec<PathBuf>) {
+	if sources.iter().any(|source| source == path) {
+		return
+	}
+	let contents = if let Ok(contents) = fs::read_to_string(path) {
+		contents
+	} else {
+		return
+	};
+	sources.push(path.to_path_buf());
+
+	let dir = path.parent().unwrap_or_else(|| Path::new(""));
+	for line in contents.lines() {
+		let line = line.trim_start();
+		let imports = if let Some(imports) = line.strip_prefix("@import") {
+			imports
+		} else if let Some(imports) = line.strip_prefix("@use").or_else(|| line.strip_prefix("@forward")) {
+			imports
+		} else {
+			continue
+		};
+
+		for import in imports.split(',') {
+			let import = import.trim();
+			let quote = match import.chars().next() {
+				Some(quote) if quote == '\'' || quote == '"' => quote,
+				_ => continue,
+			};
+			let name = import[1..].split(quote).next().unwrap_or("");
+			let base = dir.join(name);
+			let stem = base.file_name().unwrap_or_else(|| OsStr::new("")).to_string_lossy().to_string();
+			let parent = base.parent().unwrap_or_else(|| Path::new(""));
+			for candidate in &[
+				base.to_path_buf(),
+				base.with_extension("scss"),
+				base.with_extension("sass"),
+				parent.join(["_", &stem, ".scss"].concat()),
+				parent.join(["_", &stem, ".sass"].concat()),
+				base.join("_index.scss"),
+				base.join("index.scss"),
+			] {
+				if candidate.is_file() {
+					stylesheet_sources(candidate, sources);
+				}
+			}
+		}
+	}
+}
+
+fn stylesheet_key(config: &Config) -> String {
+	let mut sources = Vec::new();
+	stylesheet_sources(&config.katsite_essentials.stylesheet, &mut sources);
+
+	let mut parts = vec![
+		vec![u8::from(config.katsite_essentials.minifier), u8::from(config.katsite_essentials.brotli)],
+	];
+	sources.extend(config.markdown.highlight_stylesheet(&config.files.output_dir));
+	for source in sources {
+		parts.push(source.to_string_lossy().as_bytes().to_vec());
+		parts.push(fs::read(&source).unwrap_or_default());
+	}
+	hash_parts(&parts)
+}
+
+// Whether a template could read the contents of pages other than the one it's rendering, like `site.pages[0].data`, or `post.data` while looping over a collection. Only `page.data` is the rendered page's own contents.
+fn reads_site_contents(template: &str) -> bool {
+	let mut rest = template;
+	while let Some(start) = rest.find("{{").into_iter().chain(rest.find("{%")).min() {
+		rest = &rest[start + 2..];
+		let end = rest.find("}}").into_iter().chain(rest.find("%}")).min().unwrap_or(rest.len());
+		if rest[..end].replace("page.data", "").contains("data") {
+			return true
+		}
+		rest = &rest[end..];
+	}
+	false
+}
+
+// Timestamps are left out, as are page contents unless a template reads them from `site`, so that editing one page doesn't re-render every other page.
+fn site_key(config: &Config, layout: &str, site: &Site, contents: bool) -> String {
+	let mut parts = vec![config.raw.to_owned(), layout.to_owned()];
+	parts.extend(site.generated.iter().map(|page| page.path_raw.to_owned()));
+	for page in &site.pages {
+		let mut meta = page.clone();
+		meta.data = if contents { hash_parts(&[&page.data]) } else { String::new() };
+		meta.created_time = 0;
+		meta.modified_time = 0;
+		parts.push(to_toml(&meta).unwrap_or_default());
+	}
+	hash_parts(&parts)
+}
+
+fn compress_file(path: &Path, mode: BrotliEncoderMode) -> Result<(), Failure> {
+	let mut input_file = File::open(path).map_err(|err| Failure::new(path, format!("Unable to open file! {}", err)))?;
+	let input_size = input_file.metadata().map(|m| m.len() as usize).unwrap_or(0);
+	let mut input = Vec::with_capacity(input_size + 1);
+	input_file.read_to_end(&mut input).map_err(|err| Failure::new(path, format!("Unable to read file! {}", err)))?;
+
+	let output_path = path.with_extension([&path.extension().unwrap_or_else(|| OsStr::new("")).to_string_lossy(), ".br"].concat());
+	let mut output = File::create(&output_path).map_err(|err| Failure::new(&output_path, format!("Unable to create file! {}", err)))?;
+	let params = BrotliEncoderParams {
+		dist: BrotliDistanceParams {
+			distance_postfix_bits: 0,
+			num_direct_distance_codes: 0,
+			alphabet_size: BROTLI_DISTANCE_ALPHABET_SIZE(0, 0, BROTLI_MAX_DISTANCE_BITS),
+			max_distance: BROTLI_MAX_DISTANCE,
+		},
+		mode,
+		quality: 11,
+		q9_5: false,
+		lgwin: 24,
+		lgblock: 0,
+		size_hint: input_size,
+		disable_literal_context_modeling: 0,
+		hasher: BrotliHasherParams {
+			type_: 6,
+			block_bits: 11 - 1,
+			bucket_bits: 15,
+			hash_len: 5,
+			num_last_distances_to_check: 16,
+			literal_byte_score: 0,
+		},
+		log_meta_block: false,
+		stride_detection_quality: 0,
+		high_entropy_detection_quality: 0,
+		cdf_adaptation_detection: 0,
+		prior_bitmask_detection: 0,
+		literal_adaptation: [(0,0);4],
+		large_window: false,
+		avoid_distance_prefix_search:false,
+		catable: false,
+		use_dictionary: true,
+		appendable: false,
+		magic_number: false,
+		favor_cpu_efficiency: false,
+	};
+	CompressorWriter::with_params(&mut output, 4096, &params).write_all(&input)
+		.map_err(|err| Failure::new(&output_path, format!("Unable to write to file! {}", err)))?;
+	if output.metadata().map(|m| m.len() as usize).unwrap_or(0) > input_size {
+		let _ = fs::remove_file(output_path);
+	}
+	Ok(())
+}
+
+fn relative_path(path: &Path) -> Vec<String> {
+	path.components().filter_map(|component| {
+		match component {
+			Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+			_ => None,
+		}
+	}).collect()
+}
+
+fn load_pageinfo<P: AsRef<Path>>(config: &Config, path: P, cache: &PageCache) -> Result<Page, Failure> {
+	let path = &path.as_ref();
+	let metadata = path.metadata();
+
+	let html_file = relative_path(path).iter().collect::<PathBuf>().with_extension("html");
+	let segments = relative_path(&html_file);
+	let dir_segments = &segments[..segments.len().saturating_sub(1)];
+	let path_url = segments.iter().map(|segment| encode(segment)).collect::<Vec<_>>().join("/");
+	let file_stem = path.file_stem().unwrap_or_else(|| {
+		path.extension().unwrap_or_else(|| OsStr::new(".html"))
+	}).to_string_lossy();
+	let file_name = html_file.file_name().unwrap().to_string_lossy();
+
+	let mut contents = fs::read_to_string(config.files.output_dir.join(&html_file))
+		.map_err(|err| Failure::new(path, format!("Unable to open converted page! {}", err)))?;
+
+	if let Some(cached) = cache.pages.get(&segments.join("/")) {
+		if cached.output == hash_parts(&[&contents]) {
+			let mut page = cached.page.clone();
+			page.cached = true;
+			return Ok(page)
+		}
+	}
+
+	let toc = if let Some(start) = contents.rfind(TOC_START) {
+		let toc: Toc = toml::from_str(contents[start + TOC_START.len()..].trim_end().trim_end_matches("-->"))
+			.map_err(|err| Failure::new(path, format!("Unable to parse table of contents! {}", err)))?;
+		contents.truncate(start);
+		toc.toc
+	} else {
+		Vec::new()
+	};
+
+	let frontmatter: FrontMatter = if let Some(start) = contents.rfind(FRONTMATTER_START) {
+		let frontmatter = toml::from_str(contents[start + FRONTMATTER_START.len()..].trim_end().trim_end_matches("-->"))
+			.map_err(|err| Failure::new(path, format!("Unable to parse frontmatter! {}", err)))?;
+		contents.truncate(start);
+		frontmatter
+	} else {
+		FrontMatter::default()
+	};
+
+	// Datetimes without a date, like `09:00:00`, can't be sorted or archived.
+	let date = match frontmatter.date {
+		None => None,
+		Some(toml::Value::Datetime(date)) => Some(date.to_string()),
+		Some(toml::Value::String(date)) => Some(date.replacen(' ', "T", 1).parse::<toml::value::Datetime>().map(|date| date.to_string()).unwrap_or_default()),
+		Some(_) => Some(String::new()),
+	};
+	if date.as_ref().is_some_and(|date| date.get(4..5) != Some("-")) {
+		return Err(Failure::new(path, "Unable to parse frontmatter! date isn't a valid date, like 2024-01-31 or 2024-01-31T09:00:00Z."))
+	}
+
+	if config.katsite_essentials.sanitizer {
+		progress!("Sanitizing {}...", path.to_string_lossy());
+		contents = config.markdown.sanitizer().clean(&contents).to_string();
+	}
+ 
+	Ok(Page {
+		cached: false,
+		created_time: {
+			if let Ok(meta) = &metadata {
+				meta.created().unwrap_or(UNIX_EPOCH)
+				.duration_since(UNIX_EPOCH).unwrap_or_else(|_| Duration::new(0, 0))
+				.as_secs()
+			} else {
+				0
+			}
+		},
+		modified_time: {
+			if let Ok(meta) = &metadata {
+				meta.modified().unwrap_or(UNIX_EPOCH)
+				.duration_since(UNIX_EPOCH).unwrap_or_else(|_| Duration::new(0, 0))
+				.as_secs()
+			} else {
+				0
+			}
+		},
+		filename: encode_attribute(&encode(&file_name)),
+		filename_url: encode(&file_name),
+		filename_raw: file_name.to_string(),
+		path: encode_attribute(&path_url),
+		path_url,
+		path_raw: segments.join("/"),
+		dir: dir_segments.join("/"),
+		root: "../".repeat(dir_segments.len()),
+		data: contents,
+		title: {
+			let title = if let Some(title) = frontmatter.title {
+				title
+			} else if file_name == "index.html" {
+				dir_segments.last().unwrap_or(&config.katsite_essentials.name).to_string()
+			} else {
+				file_stem.to_string()
+			};
+			if title.chars().count() > 65 {
+				eprintln!("Warning: {}'s title is excessively long.", path.to_string_lossy());
+			}
+			encode_attribute(&title)
+		},
+		description: {
+			if let Some(description) = frontmatter.description {
+				if description.chars().count() > 155 {
+					eprintln!("Warning: {}'s description is excessively long.", path.to_string_lossy());
+				}
+				Some(encode_attribute(&description))
+			} else {
+				None
+			}
+		},
+		locale: encode_attribute(&frontmatter.locale.unwrap_or_else(|| config.katsite_essentials.default_lang.to_owned())),
+		is_nsfw: frontmatter.is_nsfw.unwrap_or(config.katsite_essentials.default_is_nsfw),
+		allow_robots: frontmatter.allow_robots.unwrap_or(config.katsite_essentials.default_allow_robots),
+		og_type: encode_attribute(&frontmatter.og_type.unwrap_or_else(|| config.katsite_essentials.default_og_type.to_owned())),
+		og_image: frontmatter.og_image.map(|image| encode_attribute(&encode(&image))),
+		og_audio: frontmatter.og_audio.map(|audio| encode_attribute(&encode(&audio))),
+		og_video: frontmatter.og_video.map(|video| encode_attribute(&encode(&video))),
+		toc,
+		date,
+		collections: Vec::new(),
+		tags: taxonomies::terms(frontmatter.tags),
+		categories: taxonomies::terms(frontmatter.categories),
+	})
+}
+
+// A path in the output directory, as a URL relative to the site's root.
+fn url(path_raw: &str) -> String {
+	path_raw.split('/').map(encode).collect::<Vec<_>>().join("/")
+}
+
+// A page rendered from a collection's or taxonomy's template, rather than converted from an input file.
+struct Generated {
+	page: Page,
+	template: PathBuf,
+	// Variables for the template, alongside `site` and `page`.
+	globals: liquid::Object,
+	// The contents of every page it lists, so that it's re-rendered when one of them changes.
+	contents: String,
+}
+
+impl Generated {
+	fn contents<'a, I: IntoIterator<Item = &'a Page>>(pages: I) -> String {
+		pages.into_iter().map(|page| page.data.as_str()).collect()
+	}
+}
+
+// Resolves `.` and `..` in a path in the output directory, returning `None` if it points outside of it.
+fn normalize_path(path_raw: &str) -> Option<String> {
+	let mut segments = Vec::new();
+	for segment in path_raw.split('/') {
+		match segment {
+			"" | "." => (),
+			".." => drop(segments.pop()?),
+			_ => segments.push(segment),
+		}
+	}
+	Some(segments.join("/"))
+}
+
+// Every page generated for collections and taxonomies, leaving out any that would overwrite another page or be written outside of the output directory.
+fn generated_pages(config: &Config, site: &Site, failures: &mut Vec<Failure>) -> Vec<Generated> {
+	let mut paths: HashSet<String> = site.pages.iter().map(|page| page.path_raw.to_owned()).collect();
+	let generated = site.collections.values().flat_map(|collection| collections::generate(config, collection));
+	generated.chain(taxonomies::generate(config, &site.taxonomies)).filter_map(|mut generated| {
+		let path_raw = if let Some(path_raw) = normalize_path(&generated.page.path_raw).filter(|path_raw| !path_raw.is_empty()) {
+			path_raw
+		} else {
+			failures.push(Failure::new(&generated.template, format!("Unable to create {}! It's outside of the output directory.", generated.page.path_raw)));
+			return None
+		};
+		if !paths.insert(path_raw.to_owned()) {
+			failures.push(Failure::new(&generated.template, format!("Unable to create {}! Another page is already written there.", path_raw)));
+			return None
+		}
+		if path_raw != generated.page.path_raw {
+			generated.page = Page::generated(config, &path_raw, &generated.page.title);
+		}
+		Some(generated)
+	}).collect()
+}
+
+impl Page {
+	// A page rendered from a template, rather than converted from an input file. Its title is already escaped.
+	fn generated(config: &Config, path_raw: &str, title: &str) -> Self {
+		let segments: Vec<&str> = path_raw.split('/').collect();
+		let (file_name, dir_segments) = segments.split_last().unwrap();
+		let path_url = url(path_raw);
+		Self {
+			cached: false,
+			created_time: 0,
+			modified_time: 0,
+			filename: encode_attribute(&encode(file_name)),
+			filename_url: encode(file_name),
+			filename_raw: (*file_name).to_string(),
+			path: encode_attribute(&path_url),
+			path_url,
+			path_raw: path_raw.to_string(),
+			dir: dir_segments.join("/"),
+			root: "../".repeat(dir_segments.len()),
+			data: String::new(),
+			title: title.to_string(),
+			description: None,
+			locale: encode_attribute(&config.katsite_essentials.default_lang),
+			is_nsfw: config.katsite_essentials.default_is_nsfw,
+			allow_robots: config.katsite_essentials.default_allow_robots,
+			og_type: encode_attribute(&config.katsite_essentials.default_og_type),
+			og_image: None,
+			og_audio: None,
+			og_video: None,
+			toc: Vec::new(),
+			date: None,
+			collections: Vec::new(),
+			tags: Vec::new(),
+			categories: Vec::new(),
+		}
+	}
+}
+
+fn load_siteinfo(config: &Config, cache: &PageCache, failures: &mut Vec<Failure>) -> Site {
+	let files = glob(&config.files.input_glob).map_err(|err| {
+		failures.push(Failure::new(&config.files.input_glob, format!("Unable to create file glob! {}", err)));
+	}).into_iter().flatten().par_bridge();
+
+	let patterns = collections::patterns(config, failures);
+
+	let (pages, errors): (Vec<_>, Vec<_>) = files.filter_map(Result::ok).filter(|file| {
+		// Pages that the site generator didn't convert, as they aren't published or failed to build, are left out.
+		config.files.output_dir.join(relative_path(file).iter().collect::<PathBuf>().with_extension("html")).exists()
+	}).map(|file| {
+		let mut page = load_pageinfo(config, &file, cache)?;
+		page.collections = collections::matching(&patterns, &relative_path(&file).iter().collect::<PathBuf>());
+		taxonomies::link(config, &mut page);
+		Ok(page)
+	}).partition(Result::is_ok);
+	failures.extend(errors.into_iter().filter_map(Result::err));
+
+	let mut pages: Vec<Page> = pages.into_iter().filter_map(Result::ok).collect();
+	pages.par_sort_unstable_by_key(|a| a.title.to_owned());
+
+	Site {
+		name: encode_attribute(&config.katsite_essentials.name),
+		url_stub: config.katsite_essentials.url_stub.to_owned(),
+		collections: collections::load(config, &pages),
+		taxonomies: taxonomies::load(config, &pages),
+		generated: Vec::new(),
+		pages,
+	}
+}
+
+fn load_additional_templates(site: &Site, site_key: &str, config: &Config, cache: &PageCache, failures: &mut Vec<Failure>) -> HashMap<String, String> {
+	let files = glob(&config.katsite_essentials.liquid_glob).map_err(|err| {
+		failures.push(Failure::new(&config.katsite_essentials.liquid_glob, format!("Unable to create file glob! {}", err)));
+	}).into_iter().flatten().par_bridge();
+
+	let (templates, errors): (Vec<_>, Vec<_>) = files.filter_map(Result::ok).filter_map(|file| {
+		if file.file_name() == Some(OsStr::new(&config.katsite_essentials.layout)) {
+			return None
+		}
+		// Templates for generated pages are rendered once for every page instead.
+		if config.generated_templates().iter().filter_map(|template| template.file_name()).any(|name| file.file_name() == Some(name)) {
+			return None
+		}
+		Some(render_template(&file, site, site_key, config, cache))
+	}).partition(Result::is_ok);
+	failures.extend(errors.into_iter().filter_map(Result::err));
+
+	templates.into_iter().filter_map(Result::ok).collect()
+}
+
+fn render_template(file: &Path, site: &Site, site_key: &str, config: &Config, cache: &PageCache) -> Result<(String, String), Failure> {
+	let layout = fs::read_to_string(file)
+		.map_err(|err| Failure::new(file, format!("Unable to open template! {}", err)))?;
+
+	let name = file.to_string_lossy().to_string();
+	let key = hash_parts(&[site_key, &layout]);
+	let output_path = config.files.output_dir.join(file.file_stem().unwrap());
+	if output_path.exists() && cache.templates.get(&name) == Some(&key) {
+		if verbose() {
+			progress!("Skipping {} (unchanged)...", file.file_stem().unwrap().to_string_lossy());
+		}
+		return Ok((name, key))
+	}
+
+	progress!("Formatting {}...", file.file_stem().unwrap().to_string_lossy());
+
+	let template = ParserBuilder::with_stdlib()
+		.build().map_err(|err| Failure::new(file, format!("Unable to create liquid parser! {}", err)))?
+		.parse(&layout).map_err(|err| Failure::new(file, format!("Unable to parse template! {}", err)))?;
+
+
+	let globals = liquid::object!({
+		"site": site,
+	});
+
+	let output = template.render(&globals).map_err(|err| Failure::new(file, format!("Unable to render template! {}", err)))?;
+
+	fs::write(&output_path, output).map_err(|err| Failure::new(&output_path, format!("Unable to create file! {}", err)))?;
+
+	if config.katsite_essentials.brotli {
+		progress!("Compressing {}...", file.file_stem().unwrap().to_string_lossy());
+		compress_file(&output_path, BrotliEncoderMode::BROTLI_MODE_TEXT)?;
+	}
+
+	Ok((name, key))
+}
+
+fn parse_template(file: &Path) -> Result<(String, liquid::Template), Failure> {
+	let source = fs::read_to_string(file)
+		.map_err(|err| Failure::new(file, format!("Unable to open template! {}", err)))?;
+	let template = ParserBuilder::with_stdlib()
+		.build().map_err(|err| Failure::new(file, format!("Unable to create liquid parser! {}", err)))?
+		.parse(&source).map_err(|err| Failure::new(file, format!("Unable to parse template! {}", err)))?;
+	Ok((source, template))
+}
+
+// Minifies a rendered page, then writes and compresses it. Returns the page as it was written.
+fn write_page(config: &Config, path_raw: &str, mut input: Vec<u8>) -> Result<Vec<u8>, Failure> {
+	if config.katsite_essentials.minifier {
+		progress!("Minifying {}...", path_raw);
+		let cfg = &Cfg {
+			minify_js: true,
+		};
+		truncate(&mut input, cfg)
+			.map_err(|err| Failure::new(path_raw, format!("Unable to minify page! {:?}", err)))?;
+	}
+
+	let path = config.files.output_dir.join(path_raw);
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)
+			.map_err(|err| Failure::new(parent, format!("Unable to create directory! {}", err)))?;
+	}
+
+	fs::write(&path, &input)
+		.map_err(|err| Failure::new(path_raw, format!("Unable to write page! {}", err)))?;
+
+	if config.katsite_essentials.brotli {
+		progress!("Compressing {}...", path_raw);
+		compress_file(&path, BrotliEncoderMode::BROTLI_MODE_TEXT)?;
+	}
+	Ok(input)
+}
+
+fn create_favicons(config: &Config, failures: &mut Vec<Failure>) {
+	progress!("Parsing {}...", config.katsite_essentials.favicon.to_string_lossy());
+	let icon1 = match image::open(&config.katsite_essentials.favicon) {
+		Ok(icon) => icon,
+		Err(err) => return failures.push(Failure::new(&config.katsite_essentials.favicon, format!("Unable to read favicon! {}", err))),
+	};
+	let icon2 = icon1.to_owned();
+
+	let minifier = config.katsite_essentials.minifier;
+	let brotli = config.katsite_essentials.brotli;
+	let output1 = config.files.output_dir.join("apple-touch-icon.png");
+	let output2 = config.files.output_dir.join("favicon.png");
+
+	let mut options1 = Options::from_preset(6);
+	options1.fix_errors = true;
+	options1.strip = All;
+	options1.deflate = Zopfli;
+	let options2 = options1.to_owned();
+
+	let thread1 = thread::spawn(move || {
+		progress!("Creating apple-touch-icon.png...");
+
+		let mut icon = icon1.resize_to_fill(192, 192, Lanczos3).to_rgba8();
+
+		#[allow(deprecated)]
+		let nq = NeuQuant::new(1, 64, icon.to_owned().into_flat_samples().as_slice());
+		for pixel in icon.pixels_mut() {
+			nq.map_color(pixel);
+		}
+
+		icon.save(&output1).map_err(|err| Failure::new(&output1, format!("Unable to create file! {}", err)))?;
+
+		if minifier {
+			progress!("Minifying apple-touch-icon.png...");
+			optimize(&InFile::Path(output1.to_owned()), &OutFile::Path(None), &options1)
+			.map_err(|err| Failure::new(&output1, format!("Unable to minify file! {}", err)))?;
+		}
+
+		if !brotli {
+			return Ok(())
+		}
+
+		progress!("Compressing apple-touch-icon.png...");
+		compress_file(&output1, BrotliEncoderMode::BROTLI_MODE_GENERIC)
+	});
+	
+	let thread2 = thread::spawn(move || {
+		progress!("Creating favicon.png...");
+
+		let mut icon = icon2.resize_to_fill(48, 48, Lanczos3).to_rgba8();
+
+		#[allow(deprecated)]
+		let nq = NeuQuant::new(1, 16, icon.to_owned().into_flat_samples().as_slice());
+		for pixel in icon.pixels_mut() {
+			nq.map_color(pixel);
+		}
+
+		icon.save(&output2).map_err(|err| Failure::new(&output2, format!("Unable to create file! {}", err)))?;
+
+		if minifier {
+			progress!("Minifying favicon.png...");
+			optimize(&InFile::Path(output2.to_owned()), &OutFile::Path(None), &options2)
+			.map_err(|err| Failure::new(&output2, format!("Unable to minify file! {}", err)))?;
+		}
+
+		if !brotli {
+			return Ok(())
+		}
+
+		progress!("Compressing favicon.png...");
+		compress_file(&output2, BrotliEncoderMode::BROTLI_MODE_GENERIC)
+	});
+
+	for thread in [thread1, thread2] {
+		if let Ok(Err(failure)) = thread.join() {
+			failures.push(failure);
+		}
+	}
+}
+
+fn finish(failures: Vec<Failure>) -> Result<(), Vec<Failure>> {
+	if failures.is_empty() {
+		Ok(())
+	} else {
+		Err(failures)
+	}
+}
+
+/// Compiles the stylesheet and creates favicons, skipping them if they're unchanged since the last build.
+///
+/// # Errors
+///
+/// Returns every file that couldn't be built. The rest are still built.
+pub fn asyncinit(config: &Config) -> Result<(), Vec<Failure>> {
+	let cache: AssetCache = load_cache(config, ASSET_CACHE);
+
+	let minifier = config.katsite_essentials.minifier;
+	let brotli = config.katsite_essentials.brotli;
+	let output_dir = config.files.output_dir.to_owned();
+	let stylesheet = config.katsite_essentials.stylesheet.to_owned();
+	let stylesheet_key = stylesheet_key(config);
+	let stylesheet_cached = cache.stylesheet.as_ref() == Some(&stylesheet_key);
+	let highlight_stylesheet = config.markdown.highlight_stylesheet(&output_dir);
+	let thread = thread::spawn(move || {
+		if !stylesheet.exists() {
+			return Ok(None)
+		}
+
+		if stylesheet_cached && output_dir.join("style.css").exists() {
+			if verbose() {
+				progress!("Skipping {} (unchanged)...", stylesheet.to_string_lossy());
+			}
+			return Ok(Some(stylesheet_key))
+		}
+
+		progress!("Compiling {}...", stylesheet.to_string_lossy());
+
+		let mut output = compile_file(&stylesheet, sass_rs::Options{
+			output_style: Expanded,
+			precision: 2,
+			indented_syntax: false,
+			include_paths: vec![],
+		}).map_err(|err| Failure::new(&stylesheet, format!("Unable to parse stylesheet! {}", err)))?;
+		if let Some(path) = highlight_stylesheet {
+			let highlight = fs::read_to_string(&path).map_err(|err| Failure::new(&path, format!("Unable to read highlighting stylesheet! {}", err)))?;
+			output.push('\n');
+			output.push_str(&highlight);
+		}
+
+		let output_file = output_dir.join("style.css");
+		fs::write(&output_file, output).map_err(|err| Failure::new(&output_file, format!("Unable to write stylesheet! {}", err)))?;
+
+		if minifier {
+			progress!("Minifying {}...", stylesheet.to_string_lossy());
+			let mut child = Command::new("csso")
+				.arg(&output_file)
+				.arg("--output").arg(&output_file)
+				.stdin(Stdio::null())
+				.stdout(Stdio::inherit())
+				.stderr(Stdio::inherit())
+				.spawn().map_err(|err| Failure::new(&output_file, format!("Unable to start CSS minifier! {}", err)))?;
+			let _ = child.wait();
+		}
+
+		if brotli {
+			progress!("Compressing {}...", stylesheet.to_string_lossy());
+			compress_file(&output_file, BrotliEncoderMode::BROTLI_MODE_TEXT)?;
+		}
+
+		Ok(Some(stylesheet_key))
+	});
+
+	let mut failures = Vec::new();
+
+	let favicon_key = hash_parts(&[
+		fs::read(&config.katsite_essentials.favicon).unwrap_or_default(),
+		vec![u8::from(minifier), u8::from(brotli)],
+	]);
+	let favicon_cached = cache.favicon.as_ref() == Some(&favicon_key)
+		&& config.files.output_dir.join("apple-touch-icon.png").exists()
+		&& config.files.output_dir.join("favicon.png").exists();
+
+	let favicon = if !config.katsite_essentials.favicon.exists() {
+		None
+	} else if favicon_cached {
+		if verbose() {
+			progress!("Skipping {} (unchanged)...", config.katsite_essentials.favicon.to_string_lossy());
+		}
+		Some(favicon_key)
+	} else {
+		let count = failures.len();
+		create_favicons(config, &mut failures);
+		if failures.len() == count {
+			Some(favicon_key)
+		} else {
+			None
+		}
+	};
+
+	let stylesheet = match thread.join() {
+		Ok(Ok(stylesheet)) => stylesheet,
+		Ok(Err(failure)) => {
+			failures.push(failure);
+			None
+		},
+		Err(_) => None,
+	};
+
+	if let Err(failure) = save_cache(config, ASSET_CACHE, &AssetCache {
+		stylesheet,
+		favicon,
+	}) {
+		failures.push(failure);
+	}
+	finish(failures)
+}
+
+/// Renders every page and additional template, skipping them if they're unchanged since the last build.
+///
+/// # Errors
+///
+/// Returns every file that couldn't be built. The rest are still built, unless the layout itself is invalid.
+pub fn postinit(config: &Config) -> Result<(), Vec<Failure>> {
+	progress!("Creating site template...");
+
+	let layout_path = &config.katsite_essentials.layout;
+	let layout = fs::read_to_string(layout_path)
+		.map_err(|err| vec![Failure::new(layout_path, format!("Unable to open template! {}", err))])?;
+
+	let template = ParserBuilder::with_stdlib()
+		.build().map_err(|err| vec![Failure::new(layout_path, format!("Unable to create liquid parser! {}", err))])?
+		.parse(&layout).map_err(|err| vec![Failure::new(layout_path, format!("Unable to parse template! {}", err))])?;
+
+	let mut failures = Vec::new();
+	let cache: PageCache = load_cache(config, PAGE_CACHE);
+	let mut site = load_siteinfo(config, &cache, &mut failures);
+	let generated = generated_pages(config, &site, &mut failures);
+	site.generated = generated.iter().map(|generated| generated.page.to_owned()).collect();
+	let templates = glob(&config.katsite_essentials.liquid_glob).into_iter().flatten().filter_map(Result::ok);
+	let contents = reads_site_contents(&layout) || templates.filter_map(|file| fs::read_to_string(file).ok()).any(|template| reads_site_contents(&template));
+	let site_key = site_key(config, &layout, &site, contents);
+
+	let templates = load_additional_templates(&site, &site_key, config, &cache, &mut failures);
+
+	let (pages, errors): (Vec<_>, Vec<_>) = site.pages.par_iter().map(|page| {
+		let render = hash_parts(&[site_key.to_owned(), to_toml(page).unwrap_or_default()]);
+		if page.cached {
+			if let Some(cached) = cache.pages.get(&page.path_raw) {
+				if cached.render == render {
+					if verbose() {
+						progress!("Skipping {} (unchanged)...", page.path_raw);
+					}
+					return Ok((page.path_raw.to_owned(), cached.to_owned()))
+				}
+			}
+		}
+
+		progress!("Formatting {}...", page.path_raw);
+
+		let globals = liquid::object!({
+			"page": page,
+			"site": site,
+		});
+
+		let input = template.render(&globals)
+			.map_err(|err| Failure::new(&page.path_raw, format!("Unable to render template! {}", err)))?
+			.into_bytes();
+		let input = write_page(config, &page.path_raw, input)?;
+
+		Ok((page.path_raw.to_owned(), CachedPage {
+			output: hash_parts(&[&input]),
+			render,
+			page: page.to_owned(),
+		}))
+	}).partition(Result::is_ok);
+	failures.extend(errors.into_iter().filter_map(Result::err));
+
+	// Every template for generated pages is used for several pages, so it's only parsed once.
+	let mut generated_templates = HashMap::new();
+	for file in generated.iter().map(|generated| &generated.template) {
+		if !generated_templates.contains_key(file) {
+			match parse_template(file) {
+				Ok(template) => drop(generated_templates.insert(file, template)),
+				Err(failure) => failures.push(failure),
+			}
+		}
+	}
+
+	let (generated_pages, errors): (Vec<_>, Vec<_>) = generated.par_iter().filter_map(|generated| {
+		let (source, generated_template) = generated_templates.get(&generated.template)?;
+		Some((generated, source, generated_template))
+	}).map(|(generated, source, generated_template)| {
+		let path_raw = &generated.page.path_raw;
+		let key = hash_parts(&[&site_key, source, &generated.contents]);
+		if cache.generated.get(path_raw) == Some(&key) && config.files.output_dir.join(path_raw).exists() {
+			if verbose() {
+				progress!("Skipping {} (unchanged)...", path_raw);
+			}
+			return Ok((path_raw.to_owned(), key))
+		}
+
+		progress!("Formatting {}...", path_raw);
+
+		// The collection's or taxonomy's template renders the page's contents, which are then placed into the layout like any other page.
+		let mut globals = generated.globals.to_owned();
+		globals.insert("page".into(), liquid::model::to_value(&generated.page).unwrap_or_default());
+		globals.insert("site".into(), liquid::model::to_value(&site).unwrap_or_default());
+		let mut page = generated.page.to_owned();
+		page.data = generated_template.render(&globals)
+			.map_err(|err| Failure::new(&generated.template, format!("Unable to render template for {}! {}", path_raw, err)))?;
+
+		let globals = liquid::object!({
+			"page": page,
+			"site": site,
+		});
+		let input = template.render(&globals)
+			.map_err(|err| Failure::new(path_raw, format!("Unable to render template! {}", err)))?
+			.into_bytes();
+		write_page(config, path_raw, input)?;
+		Ok((path_raw.to_owned(), key))
+	}).partition(Result::is_ok);
+	failures.extend(errors.into_iter().filter_map(Result::err));
+
+	// Pages generated by the last build that aren't any more, such as list pages past the last one.
+	let paths: HashSet<&str> = site.pages.iter().chain(&site.generated).map(|page| page.path_raw.as_str()).collect();
+	for path_raw in cache.generated.keys().filter(|path_raw| !paths.contains(path_raw.as_str())) {
+		let path = config.files.output_dir.join(path_raw);
+		if path.exists() {
+			progress!("Removing {}...", path_raw);
+			let _ = fs::remove_file(&path);
+			let _ = fs::remove_file(path.with_extension("html.br"));
+			// Only removed if it's left empty.
+			if let Some(parent) = path.parent() {
+				let _ = fs::remove_dir(parent);
+			}
+		}
+	}
+
+	if let Err(failure) = save_cache(config, PAGE_CACHE, &PageCache {
+		templates,
+		pages: pages.into_iter().filter_map(Result::ok).collect(),
+		generated: generated_pages.into_iter().filter_map(Result::ok).collect(),
+	}) {
+		failures.push(failure);
+	}
+	finish(failures)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn paths_are_normalized() {
+		assert_eq!(normalize_path("posts/index.html").as_deref(), Some("posts/index.html"));
+		assert_eq!(normalize_path("./posts//./page/2.html").as_deref(), Some("posts/page/2.html"));
+		assert_eq!(normalize_path("tags/../index.html").as_deref(), Some("index.html"));
+		assert_eq!(normalize_path("tags/./index.html").as_deref(), Some("tags/index.html"));
+	}
+
+	#[test]
+	fn templates_reading_other_pages_contents_are_found() {
+		assert!(reads_site_contents("{% for post in site.collections.posts.pages %}{{ post.data }}{% endfor %}"));
+		assert!(reads_site_contents("{{site.pages[0].data | strip_html}}"));
+		assert!(!reads_site_contents("<main data-page=\"{{ page.path }}\">{{ page.data }}</main>"));
+		assert!(!reads_site_contents("{% for post in site.pages %}{{ post.title }}{% endfor %}"));
+	}
+
+	#[test]
+	fn paths_outside_the_output_directory_are_rejected() {
+		assert_eq!(normalize_path("../index.html"), None);
+		assert_eq!(normalize_path("tags/../../index.html"), None);
+	}
+}
@@ -0,0 +1,237 @@
+//! Tags and categories, listed in the `tags` and `categories` arrays in pages' frontmatter.
+//!
+//! Each page's terms are available to templates as `page.tags` and `page.categories`, and every term is gathered into `site.taxonomies`, along with the pages that use it. Each taxonomy can have an index page listing every term, and a page for each term, which are rendered from its own templates and placed into the site's layout.
+
+use crate::{collections, url, Config, Generated, Page};
+use htmlescape::encode_attribute;
+use serde_derive::{Serialize, Deserialize};
+use std::{collections::BTreeMap, fmt::Write, path::PathBuf};
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Taxonomies {
+	tags: Settings,
+	categories: Settings,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Settings {
+	title: Option<String>,
+	path: Option<String>,
+	pub index_template: Option<PathBuf>,
+	pub term_template: Option<PathBuf>,
+}
+
+impl Taxonomies {
+	/// Every taxonomy's name, default title and settings.
+	pub fn all(&self) -> Vec<(&'static str, &'static str, &Settings)> {
+		vec![("tags", "Tags", &self.tags), ("categories", "Categories", &self.categories)]
+	}
+}
+
+impl Settings {
+	// The directory that the taxonomy's generated pages are placed in.
+	fn dir(&self, name: &str) -> String {
+		self.path.as_deref().unwrap_or(name).trim_matches('/').to_string()
+	}
+}
+
+/// A term on a page. Its name is already escaped.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Term {
+	name: String,
+	slug: String,
+	// The term's page, if the taxonomy has a term template.
+	path_url: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Taxonomy {
+	name: String,
+	title: String,
+	// The page listing every term, if the taxonomy has an index template.
+	path_url: Option<String>,
+	path_raw: Option<String>,
+	terms: Vec<TermPages>,
+}
+
+#[derive(Serialize, Clone)]
+struct TermPages {
+	name: String,
+	slug: String,
+	path_url: Option<String>,
+	count: usize,
+	pages: Vec<Page>,
+}
+
+// Only letters, digits and dashes, so that a term's page can't be written outside of the taxonomy's directory. Terms without any letters or digits, like `..`, are hex-encoded instead.
+fn slug(name: &str) -> String {
+	let slug = name.to_lowercase().split(|c: char| !c.is_alphanumeric()).filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-");
+	if slug.is_empty() {
+		name.bytes().fold(String::new(), |mut slug, byte| {
+			let _ = write!(slug, "{:02x}", byte);
+			slug
+		})
+	} else {
+		slug
+	}
+}
+
+fn index_path(dir: &str) -> String {
+	if dir.is_empty() {
+		"index.html".to_string()
+	} else {
+		[dir, "/index.html"].concat()
+	}
+}
+
+fn term_path(dir: &str, slug: &str) -> String {
+	index_path(&if dir.is_empty() { slug.to_string() } else { [dir, "/", slug].concat() })
+}
+
+fn page_terms<'a>(page: &'a Page, taxonomy: &str) -> &'a [Term] {
+	if taxonomy == "tags" {
+		&page.tags
+	} else {
+		&page.categories
+	}
+}
+
+/// Turns a frontmatter array into terms, leaving out repeated ones.
+pub fn terms(names: Option<Vec<String>>) -> Vec<Term> {
+	let mut terms: Vec<Term> = Vec::new();
+	for name in names.into_iter().flatten().filter(|name| !name.trim().is_empty()) {
+		let slug = slug(name.trim());
+		if terms.iter().all(|term| term.slug != slug) {
+			terms.push(Term {
+				name: encode_attribute(name.trim()),
+				slug,
+				path_url: None,
+			});
+		}
+	}
+	terms
+}
+
+/// Links a page's terms to their pages.
+pub fn link(config: &Config, page: &mut Page) {
+	for (name, _, settings) in config.taxonomies.all() {
+		let dir = settings.dir(name);
+		let terms = if name == "tags" { &mut page.tags } else { &mut page.categories };
+		for term in terms {
+			term.path_url = settings.term_template.as_ref().map(|_| url(&term_path(&dir, &term.slug)));
+		}
+	}
+}
+
+/// Gathers every term in every taxonomy, along with the pages that use it.
+pub fn load(config: &Config, pages: &[Page]) -> BTreeMap<String, Taxonomy> {
+	config.taxonomies.all().into_iter().map(|(name, title, settings)| {
+		let mut terms: BTreeMap<&str, TermPages> = BTreeMap::new();
+		for page in pages {
+			for term in page_terms(page, name) {
+				// Terms that only differ in case or punctuation are merged, and named after their first use.
+				let entry = terms.entry(&term.slug).or_insert_with(|| TermPages {
+					name: term.name.to_owned(),
+					slug: term.slug.to_owned(),
+					path_url: term.path_url.to_owned(),
+					count: 0,
+					pages: Vec::new(),
+				});
+				entry.count += 1;
+				entry.pages.push(page.to_owned());
+			}
+		}
+		let mut terms: Vec<TermPages> = terms.into_values().collect();
+		for term in &mut terms {
+			collections::sort(&mut term.pages, false);
+		}
+
+		let path_raw = settings.index_template.as_ref().map(|_| index_path(&settings.dir(name)));
+		(name.to_string(), Taxonomy {
+			name: name.to_string(),
+			title: encode_attribute(settings.title.as_deref().unwrap_or(title)),
+			path_url: path_raw.as_deref().map(url),
+			path_raw,
+			terms,
+		})
+	}).collect()
+}
+
+/// The index page and term pages for every taxonomy.
+pub fn generate(config: &Config, taxonomies: &BTreeMap<String, Taxonomy>) -> Vec<Generated> {
+	let mut generated = Vec::new();
+	for (name, _, settings) in config.taxonomies.all() {
+		let taxonomy = &taxonomies[name];
+		if let (Some(template), Some(path_raw)) = (&settings.index_template, &taxonomy.path_raw) {
+			generated.push(Generated {
+				page: Page::generated(config, path_raw, &taxonomy.title),
+				template: template.to_owned(),
+				globals: liquid::object!({
+					"taxonomy": taxonomy,
+				}),
+				contents: String::new(),
+			});
+		}
+
+		if let Some(template) = &settings.term_template {
+			let dir = settings.dir(name);
+			for term in &taxonomy.terms {
+				generated.push(Generated {
+					page: Page::generated(config, &term_path(&dir, &term.slug), &[&taxonomy.title, ": ", &term.name].concat()),
+					template: template.to_owned(),
+					globals: liquid::object!({
+						"taxonomy": taxonomy,
+						"term": term,
+					}),
+					contents: Generated::contents(&term.pages),
+				});
+			}
+		}
+	}
+	generated
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn slugs_only_contain_letters_digits_and_dashes() {
+		assert_eq!(slug("Rust"), "rust");
+		assert_eq!(slug("web dev"), "web-dev");
+		assert_eq!(slug("  Q&A!  "), "q-a");
+		assert_eq!(slug("Ünïcode"), "ünïcode");
+	}
+
+	#[test]
+	fn slugs_without_letters_or_digits_are_hex_encoded() {
+		assert_eq!(slug(".."), "2e2e");
+		assert_eq!(slug("."), "2e");
+		assert_eq!(slug("/"), "2f");
+		assert_eq!(slug("++"), "2b2b");
+		for name in &[".", "..", "/", "../..", "./", "\\"] {
+			let slug = slug(name);
+			assert!(!slug.is_empty() && slug != "." && slug != ".." && !slug.contains(['/', '\\', '.']), "{:?} became {:?}", name, slug);
+		}
+	}
+
+	#[test]
+	fn term_pages_stay_in_the_taxonomy_directory() {
+		assert_eq!(term_path("tags", &slug("Rust")), "tags/rust/index.html");
+		assert_eq!(term_path("tags", &slug("..")), "tags/2e2e/index.html");
+		assert_eq!(term_path("tags", &slug(".")), "tags/2e/index.html");
+		assert_eq!(term_path("", &slug("Rust")), "rust/index.html");
+		assert_eq!(index_path("tags"), "tags/index.html");
+		assert_eq!(index_path(""), "index.html");
+	}
+
+	#[test]
+	fn repeated_terms_are_left_out() {
+		let terms = terms(Some(vec!["Rust".to_string(), "rust".to_string(), " ".to_string(), "R.U.S.T".to_string(), "Web dev".to_string()]));
+		let slugs: Vec<&str> = terms.iter().map(|term| term.slug.as_str()).collect();
+		assert_eq!(slugs, ["rust", "r-u-s-t", "web-dev"]);
+		assert_eq!(terms[0].name, "Rust");
+	}
+}
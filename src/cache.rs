@@ -0,0 +1,79 @@
+use crate::{config::Config, error::{Error, Result}, plugins::{manifest_path, wasm_path, PLUGIN_DIR}, shortcodes::SHORTCODE_DIR};
+use seahash::SeaHasher;
+use serde_derive::{Serialize, Deserialize};
+use std::{fs, collections::HashMap, hash::Hasher, path::{Path, PathBuf}, time::UNIX_EPOCH};
+
+/// Directory inside the output directory that build caches are stored in.
+pub const CACHE_DIR: &str = ".katsite-cache";
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Cache {
+	pub pages: HashMap<String, String>,
+	/// Metadata that plugins attached to each page, as JSON.
+	#[serde(default)]
+	pub metadata: HashMap<String, String>,
+}
+
+fn cache_path(config: &Config) -> PathBuf {
+	config.files.output_dir.join(CACHE_DIR).join("katsite.toml")
+}
+
+pub fn load_cache(config: &Config) -> Cache {
+	if !config.files.incremental {
+		return Cache::default()
+	}
+	fs::read_to_string(cache_path(config)).ok()
+		.and_then(|cache| toml::from_str(&cache).ok())
+		.unwrap_or_default()
+}
+
+pub fn save_cache(config: &Config, cache: &Cache) -> Result<()> {
+	if !config.files.incremental {
+		return Ok(())
+	}
+	let path = cache_path(config);
+	let output = toml::to_string(cache).map_err(Error::Cache)?;
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent).map_err(Error::io(parent))?;
+	}
+	fs::write(&path, output).map_err(Error::io(&path))
+}
+
+pub fn hash_to_string(hasher: &SeaHasher) -> String {
+	format!("{:016x}", hasher.finish())
+}
+
+// Everything besides the input file itself that can change a page's output.
+pub fn build_key(config: &Config) -> SeaHasher {
+	let mut hasher = SeaHasher::new();
+	let config_input = config.raw.to_string();
+	hasher.write_usize(config_input.len());
+	hasher.write(config_input.as_bytes());
+	for plugin in &config.plugins_list {
+		hasher.write_usize(plugin.len());
+		hasher.write(plugin.as_bytes());
+		for path in &[Path::new(PLUGIN_DIR).join(plugin), wasm_path(plugin)] {
+			if let Ok(metadata) = path.metadata() {
+				hasher.write_u64(metadata.len());
+				hasher.write_u64(metadata.modified().ok()
+					.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+					.map_or(0, |time| time.as_secs()));
+			}
+		}
+		let manifest = fs::read(manifest_path(plugin)).unwrap_or_default();
+		hasher.write_usize(manifest.len());
+		hasher.write(&manifest);
+	}
+	// Any page could use any shortcode.
+	let mut shortcodes: Vec<PathBuf> = fs::read_dir(SHORTCODE_DIR).into_iter().flatten()
+		.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+		.collect();
+	shortcodes.sort();
+	for path in shortcodes {
+		let template = fs::read(&path).unwrap_or_default();
+		hasher.write(path.to_string_lossy().as_bytes());
+		hasher.write_usize(template.len());
+		hasher.write(&template);
+	}
+	hasher
+}
@@ -0,0 +1,449 @@
+use crate::{error::{Error, Result}, highlight};
+use serde::de;
+use serde_derive::Deserialize;
+use std::{fs, collections::HashMap, path::{Path, PathBuf}, time::Duration};
+
+/// The default config file, relative to the site's directory.
+pub const CONFIG_FILE: &str = "conf.toml";
+
+const DEFAULT_CONFIG: &str = "plugins_list = []
+[files]
+input_glob = \"./*.md\"
+output_dir = \".\"
+incremental = true
+[markdown]
+github_extensions = false
+comrak_extensions = false";
+
+/// A site's configuration, usually loaded from `conf.toml`.
+#[derive(Deserialize)]
+pub struct Config {
+	/// Plugins to load from the `plugins/` directory.
+	pub plugins_list: Vec<String>,
+	pub files: Files,
+	#[serde(default)]
+	pub markdown: Markdown,
+	#[serde(default)]
+	pub serve: Serve,
+	#[serde(default)]
+	pub plugins: Plugins,
+	/// The whole config file, including sections used by plugins.
+	#[serde(skip, default = "empty_table")]
+	pub raw: toml::Value,
+	#[serde(skip)]
+	source: Option<PathBuf>,
+}
+
+fn empty_table() -> toml::Value {
+	toml::Value::Table(toml::value::Table::new())
+}
+
+/// I/O settings.
+#[derive(Deserialize)]
+pub struct Files {
+	/// Pattern used to find input files.
+	pub input_glob: String,
+	/// Output directory, which mirrors the directory structure of the input files.
+	pub output_dir: PathBuf,
+	/// Skip unchanged pages, using the build cache stored in the output directory.
+	#[serde(default = "default_incremental")]
+	pub incremental: bool,
+	/// Check every link in the output directory after building.
+	#[serde(default)]
+	pub check_links: CheckLinks,
+	/// Build pages with `draft = true` in their frontmatter.
+	#[serde(default)]
+	pub drafts: bool,
+	/// Build pages with a `publish_date` in the future.
+	#[serde(default)]
+	pub future: bool,
+}
+
+const fn default_incremental() -> bool {
+	true
+}
+
+/// What happens to broken links found after building.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckLinks {
+	/// Don't check links while building. They can still be checked with `katsite check-links`.
+	#[default]
+	Off,
+	/// Print a warning for every page with broken links.
+	Warn,
+	/// Fail the build.
+	Fail,
+}
+
+/// Markdown parser settings. Options that are left unset follow the two presets, `github_extensions` and `comrak_extensions`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Markdown {
+	/// The parser to use. Defaults to comrak if either preset is enabled, and pulldown-cmark otherwise.
+	pub backend: Option<Backend>,
+	/// Enable header ids, strikethrough, tables, automatic links and task lists.
+	pub github_extensions: bool,
+	/// Enable curly quotes, superscript, footnotes and description lists.
+	pub comrak_extensions: bool,
+
+	/// `~~Strikethrough~~` text. Supported by both parsers.
+	pub strikethrough: Option<bool>,
+	/// GitHub-style tables. Supported by both parsers.
+	pub table: Option<bool>,
+	/// Task list items, like `- [x] Done`. Supported by both parsers.
+	pub tasklist: Option<bool>,
+	/// Footnotes, like `[^1]`. Supported by both parsers.
+	pub footnotes: Option<bool>,
+	/// Curly quotes, en and em dashes, and ellipses. Supported by both parsers.
+	pub smart: Option<bool>,
+	/// Turn bare URLs and email addresses into links. Only supported by comrak.
+	pub autolink: Option<bool>,
+	/// `^Superscript^` text. Only supported by comrak.
+	pub superscript: Option<bool>,
+	/// Description lists. Only supported by comrak.
+	pub description_lists: Option<bool>,
+	/// Give headers unique ids, so that they can be linked to. Supported by both parsers.
+	pub header_ids: Option<bool>,
+	/// Prefix for header ids. Supported by both parsers.
+	pub header_id_prefix: String,
+	/// Add a link to itself to every header with an id.
+	pub heading_anchors: bool,
+	/// Collect the headers into a table of contents, which katsite-essentials passes to templates as `page.toc`. Turns on `header_ids`.
+	pub toc: bool,
+	/// Escape HTML tags that GitHub disallows, like `<script>`. A proper HTML sanitizer should be used instead. Only supported by comrak.
+	pub tagfilter: bool,
+	/// Render every line break in a paragraph as `<br>`. Only supported by comrak.
+	pub hardbreaks: bool,
+	/// Use GitHub's `<pre lang="...">` for code blocks, instead of a `language-` class. Only supported by comrak.
+	pub github_pre_lang: bool,
+	/// Language for code blocks that don't declare one. Only supported by comrak.
+	pub default_info_string: Option<String>,
+	/// Allow raw HTML and dangerous links. When disabled, they're replaced with comments. Only supported by comrak, as pulldown-cmark always allows them.
+	#[serde(rename = "unsafe")]
+	pub unsafe_: bool,
+	/// Escape raw HTML instead of removing it, when `unsafe` is disabled. Only supported by comrak.
+	pub escape: bool,
+
+	/// Highlight fenced code blocks while building, so that pages don't need a highlighter script.
+	pub highlight: Highlight,
+	/// One of syntect's bundled themes, like `InspiredGitHub` or `base16-ocean.dark`.
+	pub highlight_theme: String,
+	/// Where the theme's stylesheet is written in the output directory, when highlighting with classes. katsite-essentials merges it into `style.css`.
+	pub highlight_stylesheet: PathBuf,
+	/// Render `$...$` and `$$...$$` TeX math to `MathML` while building, so that pages don't need a math script.
+	pub math: bool,
+	/// Turn `[[Page Title]]` and `[[file-stem#heading|label]]` into links to other pages, found by their title, file name or path. Links that don't match exactly one page fail the build.
+	pub wiki_links: bool,
+}
+
+impl Default for Markdown {
+	fn default() -> Self {
+		Self {
+			backend: None,
+			github_extensions: false,
+			comrak_extensions: false,
+			strikethrough: None,
+			table: None,
+			tasklist: None,
+			footnotes: None,
+			smart: None,
+			autolink: None,
+			superscript: None,
+			description_lists: None,
+			header_ids: None,
+			header_id_prefix: String::new(),
+			heading_anchors: false,
+			toc: false,
+			tagfilter: false,
+			hardbreaks: false,
+			github_pre_lang: false,
+			default_info_string: None,
+			unsafe_: true,
+			escape: false,
+			highlight: Highlight::default(),
+			highlight_theme: "InspiredGitHub".to_string(),
+			highlight_stylesheet: PathBuf::from("highlight.css"),
+			math: false,
+			wiki_links: false,
+		}
+	}
+}
+
+/// A Markdown parser.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+	Comrak,
+	PulldownCmark,
+}
+
+/// How fenced code blocks are highlighted.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Highlight {
+	/// Leave code blocks as they are, to be highlighted in the browser if at all.
+	#[default]
+	None,
+	/// Color code with inline styles.
+	Inline,
+	/// Mark code up with classes, which are colored by the theme's stylesheet.
+	Classes,
+}
+
+impl Backend {
+	#[must_use]
+	pub const fn as_str(self) -> &'static str {
+		match self {
+			Self::Comrak => "comrak",
+			Self::PulldownCmark => "pulldown-cmark",
+		}
+	}
+}
+
+impl Markdown {
+	/// The parser to use, picked from the presets if it isn't set.
+	#[must_use]
+	pub fn backend(&self) -> Backend {
+		self.backend.unwrap_or(if self.github_extensions || self.comrak_extensions {
+			Backend::Comrak
+		} else {
+			Backend::PulldownCmark
+		})
+	}
+
+	/// Whether headers are given ids, which they always are when building a table of contents.
+	#[must_use]
+	pub fn header_ids(&self) -> bool {
+		self.toc || self.header_ids.unwrap_or(self.github_extensions)
+	}
+
+	/// Options that are enabled, but not supported by the parser being used.
+	#[must_use]
+	pub fn unsupported(&self) -> Vec<&'static str> {
+		if self.backend() == Backend::Comrak {
+			return Vec::new()
+		}
+		let options = [
+			("autolink", self.autolink == Some(true)),
+			("superscript", self.superscript == Some(true)),
+			("description_lists", self.description_lists == Some(true)),
+			("tagfilter", self.tagfilter),
+			("hardbreaks", self.hardbreaks),
+			("github_pre_lang", self.github_pre_lang),
+			("default_info_string", self.default_info_string.is_some()),
+			("unsafe", !self.unsafe_),
+			("escape", self.escape),
+		];
+		options.iter().filter(|(_, enabled)| *enabled).map(|(option, _)| *option).collect()
+	}
+}
+
+/// Settings for the local preview server.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Serve {
+	/// Address to serve the output directory on.
+	pub address: String,
+	/// URL stub to replace with the local address. Defaults to `katsite_essentials.url_stub`.
+	pub url_stub: Option<String>,
+}
+
+impl Default for Serve {
+	fn default() -> Self {
+		Self {
+			address: "127.0.0.1:8000".to_string(),
+			url_stub: None,
+		}
+	}
+}
+
+/// How plugins are run. Every setting can be changed for a single plugin in `[plugins.<name>]`.
+#[derive(Deserialize, Clone)]
+pub struct Plugins {
+	/// Seconds a plugin may run for each file or hook before it's killed, or 0 to wait forever.
+	#[serde(default = "default_timeout")]
+	pub timeout: u64,
+	/// Instructions a WebAssembly plugin may run for each file or hook before it's stopped, or 0 for no limit.
+	#[serde(default = "default_fuel")]
+	pub fuel: u64,
+	#[serde(default)]
+	pub on_failure: FailurePolicy,
+	#[serde(flatten)]
+	pub overrides: HashMap<String, PluginOverride>,
+}
+
+const fn default_timeout() -> u64 {
+	60
+}
+
+const fn default_fuel() -> u64 {
+	10_000_000_000
+}
+
+impl Default for Plugins {
+	fn default() -> Self {
+		Self {
+			timeout: default_timeout(),
+			fuel: default_fuel(),
+			on_failure: FailurePolicy::default(),
+			overrides: HashMap::new(),
+		}
+	}
+}
+
+impl Plugins {
+	/// How long a plugin may run for, if there's a limit.
+	#[must_use]
+	pub fn timeout(&self, plugin: &str) -> Option<Duration> {
+		let timeout = self.overrides.get(plugin).and_then(|settings| settings.timeout).unwrap_or(self.timeout);
+		if timeout == 0 {
+			None
+		} else {
+			Some(Duration::from_secs(timeout))
+		}
+	}
+
+	/// How many instructions a WebAssembly plugin may run, if there's a limit.
+	#[must_use]
+	pub fn fuel(&self, plugin: &str) -> Option<u64> {
+		let fuel = self.overrides.get(plugin).and_then(|settings| settings.fuel).unwrap_or(self.fuel);
+		if fuel == 0 {
+			None
+		} else {
+			Some(fuel)
+		}
+	}
+
+	#[must_use]
+	pub fn on_failure(&self, plugin: &str) -> FailurePolicy {
+		self.overrides.get(plugin).and_then(|settings| settings.on_failure).unwrap_or(self.on_failure)
+	}
+}
+
+/// Settings for a single plugin, overriding those in `[plugins]`.
+#[derive(Deserialize, Clone, Default)]
+pub struct PluginOverride {
+	pub timeout: Option<u64>,
+	pub fuel: Option<u64>,
+	pub on_failure: Option<FailurePolicy>,
+}
+
+/// What happens when a plugin crashes, hangs, exits unsuccessfully, or reports errors.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+	/// Fail the file being built (or the build, for site-wide hooks). The rest of the site is still built.
+	#[default]
+	Fail,
+	/// Skip the file with a warning, keeping its output from the last build (or only warn, for site-wide hooks).
+	Skip,
+}
+
+impl Config {
+	/// Parses a config from a TOML string.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Config`] if the input isn't valid TOML, or is missing required settings.
+	pub fn from_toml(input: &str) -> Result<Self> {
+		Self::from_value(toml::from_str(input)?)
+	}
+
+	/// Creates a config from an already parsed TOML value.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Config`] if the value is missing required settings, or names an unknown highlighting theme.
+	pub fn from_value(raw: toml::Value) -> Result<Self> {
+		let mut config: Self = raw.to_owned().try_into()?;
+		config.raw = raw;
+		let theme = &config.markdown.highlight_theme;
+		if config.markdown.highlight != Highlight::None && highlight::stylesheet(theme).is_none() {
+			return Err(Error::Config(de::Error::custom(format!("unknown highlight_theme {:#?}, expected one of {}", theme, highlight::theme_names().join(", ")))))
+		}
+		Ok(config)
+	}
+
+	/// Loads a config file from disk.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Io`] if the file can't be read, or [`Error::Config`] if it can't be parsed.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+		let path = path.as_ref();
+		let mut config = Self::from_toml(&fs::read_to_string(path).map_err(Error::io(path))?)?;
+		config.source = Some(path.to_path_buf());
+		Ok(config)
+	}
+
+	/// The file this config was loaded from, unless it has been changed since.
+	#[must_use]
+	pub fn source(&self) -> Option<&Path> {
+		self.source.as_deref()
+	}
+
+	/// Changes a setting, given as a dotted key (like `files.output_dir` or `katsite_essentials.name`).
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Override`] if the key is invalid, or [`Error::Config`] if the new value has the wrong type.
+	pub fn set(&mut self, key: &str, value: toml::Value) -> Result<()> {
+		let invalid = |message: &str| Error::Override {
+			setting: key.to_string(),
+			message: message.to_string(),
+		};
+
+		let mut raw = self.raw.to_owned();
+		let mut parts: Vec<&str> = key.split('.').collect();
+		let last = parts.pop().filter(|last| !last.is_empty()).ok_or_else(|| invalid("the key is empty"))?;
+		let mut table = raw.as_table_mut().ok_or_else(|| invalid("the config is not a table"))?;
+		for part in parts {
+			table = table.entry(part.to_string()).or_insert_with(empty_table)
+				.as_table_mut().ok_or_else(|| invalid(&[part, " is not a table"].concat()))?;
+		}
+		table.insert(last.to_string(), value);
+
+		*self = Self::from_value(raw)?;
+		Ok(())
+	}
+
+	/// Changes a setting, given as `key=value`. The value is parsed as TOML, and treated as a string if that fails.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Override`] if there is no `=`, or the key is invalid.
+	pub fn set_str(&mut self, setting: &str) -> Result<()> {
+		let mut split = setting.splitn(2, '=');
+		let key = split.next().unwrap_or("").trim();
+		let value = split.next().ok_or_else(|| Error::Override {
+			setting: setting.to_string(),
+			message: "expected key=value".to_string(),
+		})?.trim();
+
+		let value = toml::from_str::<toml::value::Table>(&["value = ", value].concat()).ok()
+			.and_then(|mut table| table.remove("value"))
+			.unwrap_or_else(|| toml::Value::String(value.to_string()));
+		self.set(key, value)
+	}
+
+	/// The site's URL stub, which absolute links to it start with, unless it's empty. Set by `serve.url_stub`, or `katsite_essentials.url_stub`.
+	#[must_use]
+	pub fn url_stub(&self) -> Option<&str> {
+		self.serve.url_stub.as_deref()
+			.or_else(|| self.plugin_setting("katsite_essentials", "url_stub")?.as_str())
+			.filter(|url_stub| !url_stub.is_empty())
+	}
+
+	/// Looks up a setting in a plugin's section of the config file.
+	#[must_use]
+	pub fn plugin_setting(&self, plugin: &str, key: &str) -> Option<&toml::Value> {
+		self.raw.get(plugin)?.get(key)
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self::from_toml(DEFAULT_CONFIG).unwrap_or_else(|err| unreachable!("Default config is invalid: {}", err))
+	}
+}
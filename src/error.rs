@@ -0,0 +1,220 @@
+use std::{error, fmt, io, path::PathBuf};
+
+/// Everything that can go wrong while building a site.
+#[derive(Debug)]
+pub enum Error {
+	/// A file or directory could not be read or written.
+	Io {
+		path: PathBuf,
+		source: io::Error,
+	},
+	/// The config file is not valid TOML, or is missing required settings.
+	Config(toml::de::Error),
+	/// The input glob could not be parsed.
+	Glob(glob::PatternError),
+	/// The build cache could not be serialized.
+	Cache(toml::ser::Error),
+	/// A plugin could not be started, or crashed while in use.
+	Plugin {
+		plugin: String,
+		source: io::Error,
+	},
+	/// A plugin exited unsuccessfully, or was killed after running for too long.
+	PluginFailed {
+		plugin: String,
+		hook: String,
+		reason: String,
+		/// The end of the plugin's error output.
+		stderr: String,
+	},
+	/// A WebAssembly plugin could not be compiled, or isn't supported by this build.
+	Wasm {
+		plugin: String,
+		message: String,
+	},
+	/// A plugin's manifest is invalid.
+	Manifest {
+		plugin: String,
+		message: String,
+	},
+	/// The thread running a site-wide hook panicked, so its plugins may not have finished.
+	HookPanicked {
+		hook: String,
+		message: String,
+	},
+	/// A plugin sent a response that doesn't follow the plugin protocol.
+	Protocol {
+		plugin: String,
+		message: String,
+	},
+	/// A plugin reported errors in a file, or in the site.
+	Diagnostics {
+		plugin: String,
+		messages: Vec<String>,
+	},
+	/// An input file is outside of the site's directory, so its output has nowhere to go in the output directory.
+	OutsideSite,
+	/// Another input file is built to the same output file.
+	SameOutput {
+		output: PathBuf,
+		other: PathBuf,
+	},
+	/// A file's frontmatter is not valid TOML or YAML.
+	Frontmatter(toml::de::Error),
+	/// A file contains math that can't be rendered. Every message starts with the line it's on.
+	Math(Vec<String>),
+	/// A file contains shortcodes that can't be expanded. Every message starts with the line it's on.
+	Shortcode(Vec<String>),
+	/// A file contains wiki links that don't match exactly one page. Every message starts with the line it's on.
+	Links(Vec<String>),
+	/// A page contains broken links. Every message starts with the line in the page's source it's on, if it's written there as it's output, or otherwise the byte offset in the output file, like `byte 120 of output/index.html`.
+	BrokenLinks(Vec<String>),
+	/// A config override is invalid.
+	Override {
+		setting: String,
+		message: String,
+	},
+	/// The file watcher could not be started.
+	Watch(notify::Error),
+	/// The preview server could not be started.
+	Serve {
+		address: String,
+		message: String,
+	},
+	/// A single input file could not be built.
+	File {
+		path: PathBuf,
+		source: Box<Self>,
+	},
+	/// Several parts of the build failed. Everything else was still built.
+	Build(Vec<Self>),
+}
+
+impl Error {
+	pub(crate) fn io<P: Into<PathBuf>>(path: P) -> impl FnOnce(io::Error) -> Self {
+		let path = path.into();
+		move |source| Self::Io {
+			path,
+			source,
+		}
+	}
+
+	pub(crate) fn plugin(plugin: &str) -> impl FnOnce(io::Error) -> Self + '_ {
+		move |source| Self::Plugin {
+			plugin: plugin.to_string(),
+			source,
+		}
+	}
+
+	/// The plugin that caused this error, if it was caused by a plugin misbehaving or reporting errors.
+	#[must_use]
+	pub fn plugin_name(&self) -> Option<&str> {
+		match self {
+			Self::Plugin { plugin, .. } | Self::PluginFailed { plugin, .. } | Self::Protocol { plugin, .. } | Self::Diagnostics { plugin, .. } => Some(plugin),
+			Self::File { source, .. } => source.plugin_name(),
+			_ => None,
+		}
+	}
+
+	/// Combines every error from a build into one, or returns `Ok(())` if there were none.
+	pub(crate) fn collect(mut errors: Vec<Self>) -> Result<()> {
+		match errors.len() {
+			0 => Ok(()),
+			1 => Err(errors.remove(0)),
+			_ => Err(Self::Build(errors)),
+		}
+	}
+
+	/// The process exit code that best describes this error, following `sysexits.h`.
+	#[must_use]
+	pub fn exit_code(&self) -> exitcode::ExitCode {
+		match self {
+			Self::Io { .. } => exitcode::IOERR,
+			Self::Config(_) | Self::Glob(_) | Self::Override { .. } | Self::Manifest { .. } | Self::OutsideSite => exitcode::CONFIG,
+			Self::Protocol { .. } => exitcode::PROTOCOL,
+			Self::Diagnostics { .. } | Self::SameOutput { .. } | Self::Frontmatter(_) | Self::Math(_) | Self::Shortcode(_) | Self::Links(_) | Self::BrokenLinks(_) => exitcode::DATAERR,
+			Self::Cache(_) | Self::HookPanicked { .. } => exitcode::SOFTWARE,
+			Self::Plugin { .. } | Self::PluginFailed { .. } | Self::Wasm { .. } | Self::Serve { .. } => exitcode::UNAVAILABLE,
+			Self::Watch(_) => exitcode::OSERR,
+			Self::File { source, .. } => source.exit_code(),
+			Self::Build(errors) => errors.first().map_or(exitcode::SOFTWARE, Self::exit_code),
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Io { path, source } => write!(f, "Unable to access {:#?}! Additional info below:\n{}", path, source),
+			Self::Config(err) => write!(f, "Unable to parse config file! Additional info below:\n{}", err),
+			Self::Glob(err) => write!(f, "Unable to parse file glob! Additional info below:\n{}", err),
+			Self::Cache(err) => write!(f, "Unable to serialize build cache! Additional info below:\n{}", err),
+			Self::Plugin { plugin, source } => write!(f, "Plugin {} crashed during usage! Additional info below:\n{}", plugin, source),
+			Self::PluginFailed { plugin, hook, reason, stderr } => {
+				write!(f, "Plugin {} {} during the {} hook!", plugin, reason, hook)?;
+				if !stderr.trim().is_empty() {
+					write!(f, " Its error output is below:\n{}", stderr.trim_end())?;
+				}
+				Ok(())
+			},
+			Self::Wasm { plugin, message } => write!(f, "Unable to load WebAssembly plugin {}! Additional info below:\n{}", plugin, message),
+			Self::Manifest { plugin, message } => write!(f, "Unable to parse plugin {}'s manifest! Additional info below:\n{}", plugin, message),
+			Self::HookPanicked { hook, message } => write!(f, "Plugin thread panicked during the {} hook! Additional info below:\n{}", hook, message),
+			Self::Protocol { plugin, message } => write!(f, "Plugin {} sent an invalid response! Additional info below:\n{}", plugin, message),
+			Self::Diagnostics { plugin, messages } => write!(f, "Plugin {} reported errors:\n{}", plugin, messages.join("\n")),
+			Self::OutsideSite => write!(f, "Unable to build a file outside of the site's directory! Input globs must only match files inside of it, like \"./**/*.md\"."),
+			Self::SameOutput { output, other } => write!(f, "Unable to build to {:#?}! {:#?} is already built there.", output, other),
+			Self::Frontmatter(err) => write!(f, "Unable to parse frontmatter! Additional info below:\n{}", err),
+			Self::Math(messages) => write!(f, "Unable to render math! Additional info below:\n{}", messages.join("\n")),
+			Self::Shortcode(messages) => write!(f, "Unable to expand shortcodes! Additional info below:\n{}", messages.join("\n")),
+			Self::Links(messages) => write!(f, "Unable to resolve links! Additional info below:\n{}", messages.join("\n")),
+			Self::BrokenLinks(messages) => write!(f, "Unable to follow links! Additional info below:\n{}", messages.join("\n")),
+			Self::Override { setting, message } => write!(f, "Unable to apply config override {:#?}: {}!", setting, message),
+			Self::Watch(err) => write!(f, "Unable to watch for changes! Additional info below:\n{}", err),
+			Self::Serve { address, message } => write!(f, "Unable to listen on {}! Additional info below:\n{}", address, message),
+			Self::File { path, source } => write!(f, "{}: {}", path.to_string_lossy(), source),
+			Self::Build(errors) => {
+				write!(f, "Build failed with {} errors:", errors.len())?;
+				for err in errors {
+					write!(f, "\n\n{}", err)?;
+				}
+				Ok(())
+			},
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Self::Io { source, .. } | Self::Plugin { source, .. } => Some(source),
+			Self::Config(err) | Self::Frontmatter(err) => Some(err),
+			Self::Glob(err) => Some(err),
+			Self::Cache(err) => Some(err),
+			Self::Watch(err) => Some(err),
+			Self::File { source, .. } => Some(source.as_ref()),
+			Self::PluginFailed { .. } | Self::Wasm { .. } | Self::Manifest { .. } | Self::HookPanicked { .. } | Self::Protocol { .. } | Self::Diagnostics { .. } | Self::OutsideSite | Self::SameOutput { .. } | Self::Math(_) | Self::Shortcode(_) | Self::Links(_) | Self::BrokenLinks(_) | Self::Override { .. } | Self::Serve { .. } | Self::Build(_) => None,
+		}
+	}
+}
+
+impl From<toml::de::Error> for Error {
+	fn from(err: toml::de::Error) -> Self {
+		Self::Config(err)
+	}
+}
+
+impl From<glob::PatternError> for Error {
+	fn from(err: glob::PatternError) -> Self {
+		Self::Glob(err)
+	}
+}
+
+impl From<notify::Error> for Error {
+	fn from(err: notify::Error) -> Self {
+		Self::Watch(err)
+	}
+}
+
+/// A specialized `Result` type for building sites.
+pub type Result<T> = std::result::Result<T, Error>;
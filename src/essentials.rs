@@ -0,0 +1,46 @@
+use crate::{config::Config, error::{Error, Result}, plugins::{Manifest, Plugin, PluginEnv}};
+use katsite_essentials::Failure;
+use std::sync::Arc;
+
+const NAME: &str = "katsite-essentials";
+
+// katsite-essentials, run in-process. Its settings are parsed once per build, instead of once per hook.
+struct Essentials {
+	manifest: Manifest,
+	config: katsite_essentials::Config,
+}
+
+pub fn load(config: &Config, manifest: Manifest) -> Result<Arc<dyn Plugin>> {
+	Ok(Arc::new(Essentials {
+		manifest,
+		config: katsite_essentials::Config::from_toml(&config.raw.to_string())?,
+	}))
+}
+
+impl Essentials {
+	fn run(&self, hook: fn(&katsite_essentials::Config) -> std::result::Result<(), Vec<Failure>>, env: &PluginEnv) -> Result<()> {
+		katsite_essentials::set_verbosity(env.verbosity.as_str());
+		hook(&self.config).map_err(|failures| Error::Diagnostics {
+			plugin: NAME.to_string(),
+			messages: failures.iter().map(ToString::to_string).collect(),
+		})
+	}
+}
+
+impl Plugin for Essentials {
+	fn name(&self) -> &str {
+		NAME
+	}
+
+	fn manifest(&self) -> &Manifest {
+		&self.manifest
+	}
+
+	fn asyncinit(&self, env: &PluginEnv) -> Result<()> {
+		self.run(katsite_essentials::asyncinit, env)
+	}
+
+	fn postinit(&self, env: &PluginEnv) -> Result<()> {
+		self.run(katsite_essentials::postinit, env)
+	}
+}
@@ -0,0 +1,516 @@
+use crate::{error::{Error, Result}, frontmatter, plugins::{Manifest, Page, Plugin, PluginEnv, CONFIG_ENV, PLUGIN_DIR, VERBOSITY_ENV}, protocol::{self, Level, Request, Response}, site::Verbosity};
+use std::{mem, thread, path::Path, io::{self, BufReader, Read, Write}, process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio}, sync::{mpsc::{self, RecvTimeoutError}, Arc, Condvar, Mutex, MutexGuard, PoisonError}, time::Duration};
+use wait_timeout::ChildExt;
+#[cfg(feature = "wasm")]
+use crate::wasm::WasmModule;
+
+// Only the end of a plugin's error output is kept for error reports.
+pub(crate) const STDERR_LIMIT: usize = 4096;
+
+// Forwards a plugin's error output as it's written, keeping the end of it for error reports.
+struct StderrTail {
+	buffer: Arc<Mutex<Vec<u8>>>,
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+impl StderrTail {
+	fn new(stderr: Option<ChildStderr>) -> Self {
+		let buffer = Arc::new(Mutex::new(Vec::new()));
+		let thread = stderr.map(|mut stderr| {
+			let buffer = Arc::clone(&buffer);
+			thread::spawn(move || {
+				let mut chunk = [0; 1024];
+				while let Ok(length) = stderr.read(&mut chunk) {
+					if length == 0 {
+						break
+					}
+					let _ = io::stderr().write_all(&chunk[..length]);
+					let mut buffer = buffer.lock().unwrap_or_else(PoisonError::into_inner);
+					buffer.extend_from_slice(&chunk[..length]);
+					let excess = buffer.len().saturating_sub(STDERR_LIMIT);
+					buffer.drain(..excess);
+				}
+			})
+		});
+		Self {
+			buffer,
+			thread,
+		}
+	}
+
+	fn contents(&self) -> String {
+		String::from_utf8_lossy(&self.buffer.lock().unwrap_or_else(PoisonError::into_inner)).to_string()
+	}
+
+	// Waits for the plugin to close its error output, and returns the end of it.
+	fn finish(&mut self) -> String {
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+		self.contents()
+	}
+}
+
+fn describe(status: ExitStatus) -> String {
+	status.code().map_or_else(|| "was killed by a signal".to_string(), |code| format!("exited with status {}", code))
+}
+
+pub(crate) fn timed_out(timeout: Duration) -> String {
+	format!("timed out after {:?}", timeout)
+}
+
+fn lock(child: &Mutex<Child>) -> MutexGuard<'_, Child> {
+	child.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Worker {
+	child: Arc<Mutex<Child>>,
+	stdin: ChildStdin,
+	stdout: BufReader<ChildStdout>,
+	stderr: StderrTail,
+}
+
+impl Worker {
+	fn send(&mut self, request: &Request) -> io::Result<Option<Vec<u8>>> {
+		protocol::write_frame(&mut self.stdin, &serde_json::to_vec(request)?)?;
+		protocol::read_frame(&mut self.stdout)
+	}
+
+	fn exchange(&mut self, plugin: &str, request: &Request, timeout: Option<Duration>) -> Result<Response> {
+		// The worker is killed if it doesn't respond in time, which unblocks the read below.
+		let watchdog = timeout.map(|timeout| {
+			let (done, finished) = mpsc::channel::<()>();
+			let child = Arc::clone(&self.child);
+			(done, thread::spawn(move || {
+				let expired = finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout);
+				if expired {
+					let _ = lock(&child).kill();
+				}
+				expired
+			}))
+		});
+		let response = self.send(request);
+		let expired = watchdog.is_some_and(|(done, thread)| {
+			drop(done);
+			thread.join().unwrap_or(false)
+		});
+
+		match response {
+			Ok(Some(response)) if !expired => parse_response(plugin, &response),
+			response => {
+				let status = {
+					let mut child = lock(&self.child);
+					let _ = child.kill();
+					child.wait().ok()
+				};
+				let reason = match (timeout, status, response) {
+					(Some(timeout), _, _) if expired => timed_out(timeout),
+					(_, Some(status), _) if !status.success() => describe(status),
+					(_, _, Err(err)) => format!("stopped responding ({})", err),
+					_ => "exited before responding".to_string(),
+				};
+				Err(Error::PluginFailed {
+					plugin: plugin.to_string(),
+					hook: request.hook.to_string(),
+					reason,
+					stderr: self.stderr.finish(),
+				})
+			},
+		}
+	}
+}
+
+#[derive(Default)]
+struct PoolState {
+	idle: Vec<Worker>,
+	started: usize,
+	// The plugin's timeout, which is also how long workers are given to exit once the build is finished.
+	timeout: Option<Duration>,
+}
+
+// Running copies of a persistent plugin, which are shared by every hook in a build and stopped once it's finished.
+struct Pool {
+	plugin: String,
+	workers: usize,
+	state: Mutex<PoolState>,
+	available: Condvar,
+}
+
+impl Pool {
+	fn new(plugin: &str, manifest: &Manifest) -> Self {
+		Self {
+			plugin: plugin.to_string(),
+			workers: manifest.workers.unwrap_or_else(rayon::current_num_threads).max(1),
+			state: Mutex::new(PoolState::default()),
+			available: Condvar::new(),
+		}
+	}
+
+	fn request(&self, request: &Request, env: &PluginEnv) -> Result<Response> {
+		let mut worker = self.take(env)?;
+		let response = worker.exchange(&self.plugin, request, env.settings.timeout(&self.plugin));
+
+		if response.is_ok() {
+			self.state.lock().unwrap_or_else(PoisonError::into_inner).idle.push(worker);
+		} else {
+			// The worker can't be trusted to be in a sane state anymore, so another one is started in its place when needed.
+			let mut child = lock(&worker.child);
+			let _ = child.kill();
+			let _ = child.wait();
+			drop(child);
+			self.state.lock().unwrap_or_else(PoisonError::into_inner).started -= 1;
+		}
+		self.available.notify_one();
+		response
+	}
+
+	// Waits for an idle worker, starting a new one if there's room for it.
+	fn take(&self, env: &PluginEnv) -> Result<Worker> {
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		state.timeout = env.settings.timeout(&self.plugin);
+		loop {
+			if let Some(worker) = state.idle.pop() {
+				return Ok(worker)
+			}
+			if state.started < self.workers {
+				state.started += 1;
+				drop(state);
+				return self.spawn(env).inspect_err(|_| {
+					self.state.lock().unwrap_or_else(PoisonError::into_inner).started -= 1;
+					self.available.notify_one();
+				})
+			}
+			state = self.available.wait(state).unwrap_or_else(PoisonError::into_inner);
+		}
+	}
+
+	fn spawn(&self, env: &PluginEnv) -> Result<Worker> {
+		let plugin = &self.plugin;
+		let mut child = command(plugin, env)
+			.arg("persistent")
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn().map_err(Error::plugin(plugin))?;
+		let missing = || Error::plugin(plugin)(io::Error::new(io::ErrorKind::BrokenPipe, "missing stdio"));
+		let stdin = child.stdin.take().ok_or_else(missing)?;
+		let stdout = BufReader::new(child.stdout.take().ok_or_else(missing)?);
+		let stderr = StderrTail::new(child.stderr.take());
+		Ok(Worker {
+			child: Arc::new(Mutex::new(child)),
+			stdin,
+			stdout,
+			stderr,
+		})
+	}
+}
+
+impl Drop for Pool {
+	fn drop(&mut self) {
+		let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+		let timeout = state.timeout;
+		for mut worker in state.idle.drain(..) {
+			// Workers should exit once their stdin is closed, and are killed if they don't in time.
+			drop(worker.stdin);
+			let mut child = lock(&worker.child);
+			let exited = match timeout {
+				Some(timeout) => child.wait_timeout(timeout),
+				None => child.wait().map(Some),
+			};
+			if matches!(exited, Ok(Some(_))) {
+				drop(child);
+				worker.stderr.finish();
+			} else {
+				// Anything the worker started may still hold its stderr open, so the thread forwarding it is left behind.
+				let _ = child.kill();
+				let _ = child.wait();
+			}
+		}
+	}
+}
+
+fn parse_response(plugin: &str, output: &[u8]) -> Result<Response> {
+	if output.iter().all(u8::is_ascii_whitespace) {
+		return Ok(Response::default())
+	}
+	serde_json::from_slice(output).map_err(|err| Error::Protocol {
+		plugin: plugin.to_string(),
+		message: err.to_string(),
+	})
+}
+
+fn command(plugin: &str, env: &PluginEnv) -> Command {
+	let mut command = Command::new(Path::new(PLUGIN_DIR).join(plugin));
+	command.env(CONFIG_ENV, &env.config_path)
+		.env(VERBOSITY_ENV, env.verbosity.as_str());
+	command
+}
+
+fn stdout(env: &PluginEnv) -> Stdio {
+	if env.verbosity == Verbosity::Quiet {
+		Stdio::null()
+	} else {
+		Stdio::inherit()
+	}
+}
+
+/// A plugin program in the `plugins/` directory, which is started for every hook it handles, or once per build if it's persistent.
+///
+/// WebAssembly plugins are run the same way, as a WASI command inside a sandbox.
+pub struct ExternalPlugin {
+	name: String,
+	manifest: Manifest,
+	pool: Option<Pool>,
+	#[cfg(feature = "wasm")]
+	module: Option<WasmModule>,
+}
+
+impl ExternalPlugin {
+	#[must_use]
+	pub fn new(name: &str, manifest: Manifest) -> Self {
+		let pool = if manifest.persistent {
+			Some(Pool::new(name, &manifest))
+		} else {
+			None
+		};
+		Self {
+			name: name.to_string(),
+			manifest,
+			pool,
+			#[cfg(feature = "wasm")]
+			module: None,
+		}
+	}
+
+	#[cfg(feature = "wasm")]
+	pub(crate) fn wasm(name: &str, manifest: Manifest, module: WasmModule) -> Self {
+		Self {
+			name: name.to_string(),
+			manifest,
+			pool: None,
+			module: Some(module),
+		}
+	}
+
+	// Starts a plugin and waits for it to finish, killing it if it runs for too long. If there's any input, it's written to the plugin's stdin and the plugin's stdout is returned.
+	fn run(&self, hook: &str, args: &[&str], input: Option<Vec<u8>>, env: &PluginEnv) -> Result<Vec<u8>> {
+		let plugin = self.name.as_str();
+		#[cfg(feature = "wasm")]
+		if let Some(module) = &self.module {
+			return module.run(plugin, hook, args, input, env)
+		}
+
+		let piped = input.is_some();
+		let mut child = command(plugin, env)
+			.args(args)
+			.stdin(if piped { Stdio::piped() } else { Stdio::null() })
+			.stdout(if piped { Stdio::piped() } else { stdout(env) })
+			.stderr(Stdio::piped())
+			.spawn().map_err(Error::plugin(plugin))?;
+		let mut stderr = StderrTail::new(child.stderr.take());
+
+		// Writing and reading happen on their own threads, so that a plugin that stops reading or writing can't stall the build past its timeout.
+		let writer = child.stdin.take().zip(input).map(|(mut stdin, input)| thread::spawn(move || {
+			// A plugin may exit without reading all of its input, which its exit status reports if it's a problem.
+			let _ = stdin.write_all(&input);
+		}));
+		let reader = child.stdout.take().map(|mut stdout| thread::spawn(move || {
+			let mut output = Vec::new();
+			stdout.read_to_end(&mut output).map(|_| output)
+		}));
+
+		let status = if let Some(timeout) = env.settings.timeout(plugin) {
+			if let Some(status) = child.wait_timeout(timeout).map_err(Error::plugin(plugin))? {
+				status
+			} else {
+				let _ = child.kill();
+				let _ = child.wait();
+				// Anything the plugin started may still hold its stdio open, so the threads using it are left behind.
+				return Err(Error::PluginFailed {
+					plugin: plugin.to_string(),
+					hook: hook.to_string(),
+					reason: timed_out(timeout),
+					stderr: stderr.contents(),
+				})
+			}
+		} else {
+			child.wait().map_err(Error::plugin(plugin))?
+		};
+		if let Some(writer) = writer {
+			let _ = writer.join();
+		}
+		let output = reader.map_or_else(|| Ok(Vec::new()), |reader| reader.join().unwrap_or_else(|_| Ok(Vec::new())));
+		let stderr = stderr.finish();
+		if !status.success() {
+			return Err(Error::PluginFailed {
+				plugin: plugin.to_string(),
+				hook: hook.to_string(),
+				reason: describe(status),
+				stderr,
+			})
+		}
+		output.map_err(Error::plugin(plugin))
+	}
+
+	fn exchange(&self, args: &[&str], request: &Request, env: &PluginEnv) -> Result<Response> {
+		if let Some(pool) = &self.pool {
+			return pool.request(request, env)
+		}
+
+		let input = serde_json::to_vec(request).map_err(|err| Error::plugin(&self.name)(err.into()))?;
+		let output = self.run(request.hook, args, Some(input), env)?;
+		parse_response(&self.name, &output)
+	}
+
+	// Prints warnings, and turns errors into a build failure.
+	fn report(&self, file: Option<&Path>, response: &Response, env: &PluginEnv) -> Result<()> {
+		let plugin = self.name.as_str();
+		let mut errors = Vec::new();
+		for diagnostic in &response.diagnostics {
+			let location = match (file, diagnostic.line) {
+				(Some(file), Some(line)) => format!("{}:{}: ", file.to_string_lossy(), line),
+				(Some(file), None) => format!("{}: ", file.to_string_lossy()),
+				(None, _) => String::new(),
+			};
+			let message = [&location, diagnostic.message.as_str()].concat();
+			match diagnostic.level {
+				Level::Error => errors.push(message),
+				Level::Warning => eprintln!("Warning: {} ({})", message, plugin),
+				Level::Info => if env.verbosity != Verbosity::Quiet {
+					println!("{} ({})", message, plugin);
+				},
+			}
+		}
+
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(Error::Diagnostics {
+				plugin: plugin.to_string(),
+				messages: errors,
+			})
+		}
+	}
+
+	fn init(&self, hook: &str, env: &PluginEnv) -> Result<()> {
+		if self.manifest.protocol >= 2 {
+			let response = self.exchange(&[hook], &Request {
+				protocol: protocol::VERSION,
+				hook,
+				source: None,
+				output: None,
+				content: None,
+				frontmatter: None,
+				config: env.config_section(&self.name),
+				pages: &env.pages,
+			}, env)?;
+			return self.report(None, &response, env)
+		}
+
+		self.run(hook, &[hook], None, env).map(|_| ())
+	}
+
+	fn page(&self, hook: &str, page: &mut Page, env: &PluginEnv) -> Result<()> {
+		let source = page.source;
+		let filename = source.to_string_lossy();
+		if self.manifest.protocol >= 2 {
+			let content = String::from_utf8_lossy(&page.content).to_string();
+			let parsed;
+			let frontmatter = if let Some(frontmatter) = page.frontmatter {
+				frontmatter
+			} else {
+				parsed = frontmatter::parse(&content).map_err(Error::Frontmatter)?;
+				&parsed
+			};
+			let response = self.exchange(&[hook, &filename], &Request {
+				protocol: protocol::VERSION,
+				hook,
+				source: Some(source),
+				output: Some(page.output),
+				content: Some(&content),
+				frontmatter: Some(frontmatter),
+				config: env.config_section(&self.name),
+				pages: &env.pages,
+			}, env)?;
+			self.report(Some(source), &response, env)?;
+			if let Some(content) = response.content {
+				page.content = content.into_bytes();
+			}
+			page.metadata.extend(response.metadata);
+			return Ok(())
+		}
+
+		page.content = self.run(hook, &[hook, &filename], Some(mem::take(&mut page.content)), env)?;
+		Ok(())
+	}
+}
+
+impl Plugin for ExternalPlugin {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn manifest(&self) -> &Manifest {
+		&self.manifest
+	}
+
+	fn wants_pages(&self) -> bool {
+		self.manifest.protocol >= 2
+	}
+
+	fn preinit(&self, env: &PluginEnv) -> Result<()> {
+		self.init("preinit", env)
+	}
+
+	fn asyncinit(&self, env: &PluginEnv) -> Result<()> {
+		self.init("asyncinit", env)
+	}
+
+	fn postinit(&self, env: &PluginEnv) -> Result<()> {
+		self.init("postinit", env)
+	}
+
+	fn markdown(&self, page: &mut Page, env: &PluginEnv) -> Result<()> {
+		self.page("markdown", page, env)
+	}
+
+	fn html(&self, page: &mut Page, env: &PluginEnv) -> Result<()> {
+		self.page("html", page, env)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Instant;
+
+	#[test]
+	fn workers_ignoring_eof_are_killed_after_the_timeout() {
+		let mut child = Command::new("sh")
+			.args(["-c", "while :; do sleep 10; done"])
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn().unwrap();
+		let worker = Worker {
+			stdin: child.stdin.take().unwrap(),
+			stdout: BufReader::new(child.stdout.take().unwrap()),
+			stderr: StderrTail::new(child.stderr.take()),
+			child: Arc::new(Mutex::new(child)),
+		};
+		let pool = Pool {
+			plugin: "stubborn".to_string(),
+			workers: 1,
+			state: Mutex::new(PoolState {
+				idle: vec![worker],
+				started: 1,
+				timeout: Some(Duration::from_millis(200)),
+			}),
+			available: Condvar::new(),
+		};
+
+		let start = Instant::now();
+		drop(pool);
+		assert!(start.elapsed() < Duration::from_secs(5));
+	}
+}
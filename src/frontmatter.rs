@@ -0,0 +1,226 @@
+//! Frontmatter at the very start of a Markdown file, which is written as TOML between `+++` lines, YAML between `---` lines, or TOML in an HTML comment:
+//!
+//! ```text
+//! <!--
+//! title = "Hello"
+//! -->
+//! ```
+//!
+//! The comment form stays invisible if the file is rendered by something else. Whichever form is used, the frontmatter is left out of the rendered page, and parsed into a TOML table for plugins.
+
+use crate::config::Files;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::de::Error;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Starts the comment that holds a page's frontmatter, which is added to the end of its HTML for katsite-essentials. The frontmatter is written as TOML.
+pub const COMMENT_START: &str = "<!--frontmatter\n";
+
+/// How a file's frontmatter is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	/// TOML in an HTML comment, between `<!--` and `-->` lines.
+	Comment,
+	/// TOML between `+++` lines.
+	Toml,
+	/// YAML between `---` lines.
+	Yaml,
+}
+
+// The format of a file's frontmatter, and the line that closes it.
+fn delimiters(input: &str) -> Option<(Format, &'static str)> {
+	let first_line = input.lines().next()?;
+	// A comment that closes on its first line, like `<!-- TODO -->`, is just a comment.
+	if input.starts_with("<!--") {
+		return Some((Format::Comment, "-->")).filter(|_| !first_line.contains("-->"))
+	}
+	match first_line.trim_end() {
+		"+++" => Some((Format::Toml, "+++")),
+		"---" => Some((Format::Yaml, "---")),
+		_ => None,
+	}
+}
+
+/// Splits a file into its frontmatter, including the lines around it, and the rest of the file.
+#[must_use]
+pub fn split(input: &str) -> (&str, &str) {
+	let (format, closing) = if let Some(delimiters) = delimiters(input) {
+		delimiters
+	} else {
+		return ("", input)
+	};
+	let mut offset = 0;
+	for (index, line) in input.split_inclusive('\n').enumerate() {
+		offset += line.len();
+		if index > 0 && line.trim_end() == closing {
+			return input.split_at(offset)
+		}
+	}
+	// An unclosed comment hides the rest of the file, but an unclosed `+++` or `---` is just Markdown.
+	if format == Format::Comment {
+		(input, "")
+	} else {
+		("", input)
+	}
+}
+
+/// Finds a file's frontmatter, returning its format and contents.
+#[must_use]
+pub fn extract(input: &str) -> Option<(Format, &str)> {
+	let (frontmatter, _) = split(input);
+	let (format, closing) = delimiters(frontmatter)?;
+	let contents = frontmatter.split_once('\n').map_or("", |(_, contents)| contents);
+	let last_line = contents.trim_end().rfind('\n').map_or(0, |newline| newline + 1);
+	if contents[last_line..].trim_end() == closing {
+		Some((format, &contents[..last_line]))
+	} else {
+		Some((format, contents))
+	}
+}
+
+fn yaml_to_toml(yaml: Yaml) -> Option<toml::Value> {
+	Some(match yaml {
+		Yaml::String(string) => toml::Value::String(string),
+		Yaml::Integer(integer) => toml::Value::Integer(integer),
+		Yaml::Real(real) => toml::Value::Float(real.parse().ok()?),
+		Yaml::Boolean(boolean) => toml::Value::Boolean(boolean),
+		Yaml::Array(array) => toml::Value::Array(array.into_iter().filter_map(yaml_to_toml).collect()),
+		Yaml::Hash(hash) => toml::Value::Table(hash.into_iter().filter_map(|(key, value)| {
+			let key = match key {
+				Yaml::String(key) | Yaml::Real(key) => key,
+				Yaml::Integer(key) => key.to_string(),
+				Yaml::Boolean(key) => key.to_string(),
+				_ => return None,
+			};
+			Some((key, yaml_to_toml(value)?))
+		}).collect()),
+		// TOML has no null, so keys without a value are left out.
+		Yaml::Null | Yaml::Alias(_) | Yaml::BadValue => return None,
+	})
+}
+
+fn parse_yaml(input: &str) -> Result<toml::Value, toml::de::Error> {
+	let documents = YamlLoader::load_from_str(input).map_err(toml::de::Error::custom)?;
+	match documents.into_iter().next() {
+		Some(yaml @ Yaml::Hash(_)) => Ok(yaml_to_toml(yaml).unwrap_or_else(|| toml::Value::Table(toml::value::Table::new()))),
+		None | Some(Yaml::Null) => Ok(toml::Value::Table(toml::value::Table::new())),
+		Some(_) => Err(toml::de::Error::custom("YAML frontmatter must be a mapping, like `title: Hello`")),
+	}
+}
+
+/// Parses a file's frontmatter into a TOML table, returning an empty table if it has none.
+///
+/// # Errors
+///
+/// Returns an error if the frontmatter isn't valid TOML or YAML.
+pub fn parse(input: &str) -> Result<toml::Value, toml::de::Error> {
+	match extract(input) {
+		None => Ok(toml::Value::Table(toml::value::Table::new())),
+		Some((Format::Yaml, yaml)) => parse_yaml(yaml),
+		Some((_, frontmatter)) => toml::from_str(frontmatter),
+	}
+}
+
+/// The comment that passes a page's frontmatter to katsite-essentials.
+#[must_use]
+pub fn comment(frontmatter: &toml::Value) -> String {
+	// `-->` can only appear in strings, where it's escaped so that it doesn't end the comment early.
+	let toml = toml::to_string(frontmatter).unwrap_or_default().replace("-->", "--\\u003E");
+	[COMMENT_START, &toml, "-->\n"].concat()
+}
+
+// Converts a TOML date (like `2024-01-31`, or `2024-01-31T09:00:00+02:00`) to seconds since the Unix epoch. Dates and times without an offset are treated as UTC.
+fn timestamp(date: &str) -> Option<i64> {
+	// TOML allows a space instead of the `T`.
+	let date = date.replacen(' ', "T", 1);
+	if let Ok(date) = DateTime::parse_from_rfc3339(&date) {
+		return Some(date.timestamp())
+	}
+	NaiveDateTime::parse_from_str(&date, "%Y-%m-%dT%H:%M:%S%.f").ok()
+		.or_else(|| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+		.map(|date| date.and_utc().timestamp())
+}
+
+fn date(frontmatter: &toml::Value, key: &str) -> Result<Option<i64>, toml::de::Error> {
+	let value = if let Some(value) = frontmatter.get(key) {
+		value
+	} else {
+		return Ok(None)
+	};
+	let date = match value {
+		toml::Value::Datetime(date) => date.to_string(),
+		toml::Value::String(date) => date.to_owned(),
+		_ => String::new(),
+	};
+	timestamp(&date).map(Some).ok_or_else(|| toml::de::Error::custom(format!("{} must be a date, like 2024-01-31 or 2024-01-31T09:00:00Z", key)))
+}
+
+/// Why a page isn't published, if it isn't.
+///
+/// Pages aren't published if they have `draft = true`, a `publish_date` in the future, or an `expiry_date` in the past. Drafts and future pages are still published if the `drafts` and `future` settings are enabled.
+///
+/// # Errors
+///
+/// Returns an error if `draft` isn't a boolean, or a date is invalid.
+pub fn unpublished(frontmatter: &toml::Value, files: &Files) -> Result<Option<&'static str>, toml::de::Error> {
+	let draft = match frontmatter.get("draft") {
+		Some(toml::Value::Boolean(draft)) => *draft,
+		Some(_) => return Err(toml::de::Error::custom("draft must be true or false")),
+		None => false,
+	};
+	let now = Utc::now().timestamp();
+	Ok(if draft && !files.drafts {
+		Some("draft")
+	} else if !files.future && date(frontmatter, "publish_date")?.is_some_and(|date| date > now) {
+		Some("scheduled")
+	} else if date(frontmatter, "expiry_date")?.is_some_and(|date| date <= now) {
+		Some("expired")
+	} else {
+		None
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frontmatter_is_split_from_the_rest_of_the_file() {
+		assert_eq!(split("+++\ntitle = \"Hi\"\n+++\n# Hi\n"), ("+++\ntitle = \"Hi\"\n+++\n", "# Hi\n"));
+		assert_eq!(split("---\ntitle: Hi\n---\n# Hi\n"), ("---\ntitle: Hi\n---\n", "# Hi\n"));
+		assert_eq!(split("<!--\ntitle = \"Hi\"\n-->\n# Hi\n"), ("<!--\ntitle = \"Hi\"\n-->\n", "# Hi\n"));
+		assert_eq!(split("# Hi\n+++\n"), ("", "# Hi\n+++\n"));
+	}
+
+	#[test]
+	fn comments_closed_on_their_first_line_are_not_frontmatter() {
+		let input = "<!-- TODO -->\n# Title\n\nSome text.\n";
+		assert_eq!(split(input), ("", input));
+		assert_eq!(extract(input), None);
+		assert_eq!(parse(input).unwrap(), toml::Value::Table(toml::value::Table::new()));
+	}
+
+	#[test]
+	fn unclosed_frontmatter() {
+		// An unclosed comment still hides the rest of the file, but unclosed `+++` is Markdown.
+		assert_eq!(split("<!--\ntitle = \"Hi\"\n# Hi\n"), ("<!--\ntitle = \"Hi\"\n# Hi\n", ""));
+		assert_eq!(split("+++\n# Hi\n"), ("", "+++\n# Hi\n"));
+	}
+
+	#[test]
+	fn frontmatter_contents_are_extracted() {
+		assert_eq!(extract("+++\ntitle = \"Hi\"\n+++\n# Hi\n"), Some((Format::Toml, "title = \"Hi\"\n")));
+		assert_eq!(extract("---\ntitle: Hi\n---\n"), Some((Format::Yaml, "title: Hi\n")));
+		assert_eq!(extract("<!--\ntitle = \"Hi\"\n-->"), Some((Format::Comment, "title = \"Hi\"\n")));
+		assert_eq!(extract("+++\r\ntitle = 1\r\n+++\r\n"), Some((Format::Toml, "title = 1\r\n")));
+		assert_eq!(extract("# Hi\n"), None);
+	}
+
+	#[test]
+	fn every_format_is_parsed_into_a_table() {
+		for input in &["+++\ntitle = \"Hi\"\n+++\n", "---\ntitle: Hi\n---\n", "<!--\ntitle = \"Hi\"\n-->\n"] {
+			assert_eq!(parse(input).unwrap().get("title").and_then(toml::Value::as_str), Some("Hi"), "{:?}", input);
+		}
+		assert!(parse("---\n- a list\n---\n").is_err());
+	}
+}
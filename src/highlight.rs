@@ -0,0 +1,150 @@
+//! Build-time syntax highlighting for fenced code blocks, using syntect's bundled syntaxes and themes.
+
+use crate::config::{Highlight, Markdown};
+use std::sync::OnceLock;
+use syntect::{easy::HighlightLines, highlighting::{Color, ThemeSet}, html::{append_highlighted_html_for_styled_line, css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator, IncludeBackground}, parsing::{SyntaxReference, SyntaxSet}, util::LinesWithEndings};
+
+/// Prepended to every class used for highlighting, so that they don't clash with the site's own classes.
+pub const CLASS_PREFIX: &str = "hl-";
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed {
+	prefix: CLASS_PREFIX,
+};
+
+// How comrak and pulldown-cmark start code blocks that declare a language, depending on `github_pre_lang`.
+const BLOCK_STARTS: [&str; 2] = ["<pre><code class=\"language-", "<pre lang=\""];
+const BLOCK_END: &str = "</code></pre>";
+
+fn syntaxes() -> &'static SyntaxSet {
+	static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+	SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+	static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+	THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// The names of every bundled theme.
+#[must_use]
+pub fn theme_names() -> Vec<&'static str> {
+	themes().themes.keys().map(String::as_str).collect()
+}
+
+/// The stylesheet for highlighting with classes, or `None` if the theme doesn't exist.
+#[must_use]
+pub fn stylesheet(theme: &str) -> Option<String> {
+	themes().themes.get(theme).and_then(|theme| css_for_theme_with_class_style(theme, CLASS_STYLE).ok())
+}
+
+pub(crate) fn unescape(input: &str) -> String {
+	input.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&amp;", "&")
+}
+
+fn hex(color: Color) -> String {
+	format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+// Highlights the code in a single block, or returns `None` if highlighting is disabled or fails. The language is put where the unhighlighted block had it, which is the `<pre>` tag's `lang` attribute with `github_pre_lang`.
+fn highlight_code(syntax: &SyntaxReference, language: &str, pre_lang: bool, code: &str, options: &Markdown) -> Option<String> {
+	let (pre_attributes, code_attributes) = if pre_lang {
+		([" lang=\"", language, "\""].concat(), String::new())
+	} else {
+		(String::new(), [" class=\"language-", language, "\""].concat())
+	};
+	let mut html = match options.highlight {
+		Highlight::None => return None,
+		Highlight::Inline => {
+			let theme = themes().themes.get(&options.highlight_theme)?;
+			let background = theme.settings.background.unwrap_or(Color::WHITE);
+			let foreground = theme.settings.foreground.unwrap_or(Color::BLACK);
+			let mut html = format!("<pre{} style=\"color:{};background-color:{};\"><code{}>", pre_attributes, hex(foreground), hex(background), code_attributes);
+			let mut highlighter = HighlightLines::new(syntax, theme);
+			for line in LinesWithEndings::from(code) {
+				let regions = highlighter.highlight_line(line, syntaxes()).ok()?;
+				append_highlighted_html_for_styled_line(&regions, IncludeBackground::IfDifferent(background), &mut html).ok()?;
+			}
+			html
+		},
+		Highlight::Classes => {
+			let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes(), CLASS_STYLE);
+			for line in LinesWithEndings::from(code) {
+				generator.parse_html_for_line_which_includes_newline(line).ok()?;
+			}
+			format!("<pre{} class=\"{}code\"><code{}>{}", pre_attributes, CLASS_PREFIX, code_attributes, generator.finalize())
+		},
+	};
+	html.push_str(BLOCK_END);
+	Some(html)
+}
+
+// Highlights a whole `<pre>` element, which starts with one of `BLOCK_STARTS`.
+fn highlight_block(block: &str, start: &str, options: &Markdown) -> Option<String> {
+	let language = block[start.len()..].split('"').next()?;
+	let syntax = syntaxes().find_syntax_by_token(&unescape(language))?;
+	let code_tag = block.find("<code")?;
+	let code_start = code_tag + block[code_tag..].find('>')? + 1;
+	let code = unescape(&block[code_start..block.len() - BLOCK_END.len()]);
+	highlight_code(syntax, language, start == BLOCK_STARTS[1], &code, options)
+}
+
+/// Highlights every code block in rendered HTML that declares a known language. Other code blocks are left as they are.
+#[must_use]
+pub fn highlight_html(html: &str, options: &Markdown) -> String {
+	let mut output = String::with_capacity(html.len());
+	let mut rest = html;
+	loop {
+		let next = BLOCK_STARTS.iter().filter_map(|start| rest.find(start).map(|index| (index, *start))).min();
+		let (index, start) = if let Some(next) = next {
+			next
+		} else {
+			break
+		};
+		let length = if let Some(end) = rest[index..].find(BLOCK_END) {
+			end + BLOCK_END.len()
+		} else {
+			break
+		};
+
+		let block = &rest[index..index + length];
+		output.push_str(&rest[..index]);
+		output.push_str(&highlight_block(block, start, options).unwrap_or_else(|| block.to_string()));
+		rest = &rest[index + length..];
+	}
+	output.push_str(rest);
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn options(highlight: Highlight) -> Markdown {
+		Markdown {
+			highlight,
+			..Markdown::default()
+		}
+	}
+
+	#[test]
+	fn the_language_stays_where_it_was() {
+		for highlight in &[Highlight::Inline, Highlight::Classes] {
+			let html = highlight_html("<pre><code class=\"language-rust\">fn main() {}\n</code></pre>", &options(*highlight));
+			assert!(html.starts_with("<pre ") && html.contains("><code class=\"language-rust\">") && !html.contains("lang=\""), "{}", html);
+
+			let html = highlight_html("<pre lang=\"rust\"><code>fn main() {}\n</code></pre>", &options(*highlight));
+			assert!(html.starts_with("<pre lang=\"rust\" ") && html.contains("><code>") && !html.contains("language-"), "{}", html);
+		}
+	}
+
+	#[test]
+	fn unknown_languages_are_left_alone() {
+		let block = "<pre lang=\"nonsense\"><code>x\n</code></pre>";
+		assert_eq!(highlight_html(block, &options(Highlight::Classes)), block);
+		assert_eq!(highlight_html(block, &options(Highlight::None)), block);
+	}
+}
@@ -0,0 +1,49 @@
+#![warn(clippy::nursery)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::cargo)]
+#![allow(clippy::cargo_common_metadata)]
+#![allow(clippy::multiple_crate_versions)]
+#![allow(clippy::uninlined_format_args)]
+#![allow(clippy::unnecessary_debug_formatting)]
+#![allow(clippy::implicit_clone)]
+#![allow(clippy::manual_let_else)]
+#![allow(clippy::struct_excessive_bools)]
+#![warn(clippy::all)]
+
+//! A minimal and flexible site generator using Markdown.
+//!
+//! ```no_run
+//! let site = katsite::Builder::new().config_path("conf.toml").finish()?;
+//! let report = site.build()?;
+//! println!("Converted {} pages in {:?}", report.converted.len(), report.duration);
+//! # Ok::<(), katsite::Error>(())
+//! ```
+
+mod cache;
+pub mod config;
+pub mod error;
+#[cfg(feature = "essentials")]
+mod essentials;
+pub mod external;
+pub mod frontmatter;
+pub mod highlight;
+pub mod linkcheck;
+pub mod links;
+pub mod markdown;
+pub mod math;
+pub mod plugins;
+pub mod protocol;
+pub mod registry;
+pub mod serve;
+pub mod shortcodes;
+pub mod site;
+pub mod toc;
+#[cfg(feature = "wasm")]
+mod wasm;
+pub mod watch;
+
+pub use config::Config;
+pub use error::{Error, Result};
+pub use plugins::Plugin;
+pub use registry::Registry;
+pub use site::{Builder, BuildReport, Site, Stages, Verbosity};
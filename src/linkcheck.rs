@@ -0,0 +1,311 @@
+//! Checks that every link in the built site points somewhere.
+//!
+//! Every HTML file in the output directory is read, and the links, images, scripts and stylesheets in it are followed. Relative links and absolute links starting with the site's URL stub have to point to a file in the output directory, and `#fragment`s have to match an id in the page they point to. Links to other sites aren't checked.
+
+use crate::{error::{Error, Result}, highlight::unescape};
+use rayon::prelude::*;
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, path::{Component, Path, PathBuf}};
+use urlencoding::decode;
+
+// Elements whose contents aren't HTML, so aren't searched for links.
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+struct Reference {
+	// The byte offset of the tag in the output file. Line numbers wouldn't help much, as the output is often minified.
+	offset: usize,
+	url: String,
+}
+
+// The ids in a page, and everything it refers to.
+#[derive(Default)]
+struct Document {
+	ids: HashSet<String>,
+	references: Vec<Reference>,
+}
+
+// Whether an attribute holds a URL, or a list of them in the case of `srcset`.
+fn is_reference(tag: &str, attribute: &str) -> bool {
+	match attribute {
+		"href" => matches!(tag, "a" | "area" | "link"),
+		"src" => matches!(tag, "img" | "script" | "iframe" | "source" | "audio" | "video" | "embed" | "track" | "input"),
+		"srcset" => matches!(tag, "img" | "source"),
+		"poster" => tag == "video",
+		"data" => tag == "object",
+		_ => false,
+	}
+}
+
+// Parses a tag's attributes, starting after its name, returning them and the position after the tag. Names are lowercased and values are unescaped.
+pub(crate) fn attributes(html: &str, mut position: usize) -> (Vec<(String, String)>, usize) {
+	let bytes = html.as_bytes();
+	let mut attributes = Vec::new();
+	loop {
+		while bytes.get(position).is_some_and(u8::is_ascii_whitespace) || bytes.get(position) == Some(&b'/') {
+			position += 1;
+		}
+		if position >= bytes.len() || bytes[position] == b'>' {
+			return (attributes, position + 1)
+		}
+
+		let name_start = position;
+		while bytes.get(position).is_some_and(|byte| !byte.is_ascii_whitespace() && !matches!(byte, b'=' | b'>' | b'/')) {
+			position += 1;
+		}
+		let name = html[name_start..position].to_ascii_lowercase();
+		while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+			position += 1;
+		}
+		if bytes.get(position) != Some(&b'=') {
+			attributes.push((name, String::new()));
+			continue
+		}
+		position += 1;
+		while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+			position += 1;
+		}
+
+		let value = if let Some(quote @ (b'"' | b'\'')) = bytes.get(position) {
+			let start = position + 1;
+			let end = html[start..].find(char::from(*quote)).map_or(html.len(), |end| start + end);
+			position = end + 1;
+			&html[start..end]
+		} else {
+			let start = position;
+			while bytes.get(position).is_some_and(|byte| !byte.is_ascii_whitespace() && *byte != b'>') {
+				position += 1;
+			}
+			&html[start..position]
+		};
+		attributes.push((name, unescape(value)));
+	}
+}
+
+fn parse(html: &str) -> Document {
+	let mut document = Document::default();
+	let mut position = 0;
+	while let Some(start) = html[position..].find('<').map(|start| position + start) {
+		let rest = &html[start..];
+		let end = if rest.starts_with("<!--") {
+			rest.find("-->").map_or(html.len(), |end| start + end + 3)
+		} else if rest.starts_with("<!") || rest.starts_with("<?") || rest.starts_with("</") {
+			rest.find('>').map_or(html.len(), |end| start + end + 1)
+		} else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+			let name_end = rest[1..].find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/').map_or(rest.len(), |end| end + 1);
+			let tag = rest[1..name_end].to_ascii_lowercase();
+			let (attributes, end) = attributes(html, start + name_end);
+			for (attribute, value) in attributes {
+				if attribute == "id" || (attribute == "name" && tag == "a") {
+					document.ids.insert(value);
+				} else if attribute == "srcset" && is_reference(&tag, &attribute) {
+					for candidate in value.split(',') {
+						if let Some(url) = candidate.split_whitespace().next() {
+							document.references.push(Reference { offset: start, url: url.to_string() });
+						}
+					}
+				} else if is_reference(&tag, &attribute) {
+					document.references.push(Reference { offset: start, url: value });
+				}
+			}
+			if RAW_TEXT.contains(&tag.as_str()) {
+				let closing = ["</", &tag].concat();
+				html[end.min(html.len())..].to_ascii_lowercase().find(&closing).map_or(html.len(), |close| end + close)
+			} else {
+				end
+			}
+		} else {
+			start + 1
+		};
+		position = end.min(html.len());
+	}
+	document
+}
+
+// Whether a URL starts with a scheme like `https:` or `mailto:`, or is protocol-relative.
+fn has_scheme(url: &str) -> bool {
+	url.starts_with("//") || url.find(':').is_some_and(|colon| {
+		let scheme = &url[..colon];
+		scheme.starts_with(|c: char| c.is_ascii_alphabetic()) && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+	})
+}
+
+// Finds the file in the output directory that a link points to, and the fragment it points to in that file, if any. Links to other sites return `None`.
+fn resolve(url: &str, page: &Path, output_dir: &Path, url_stub: Option<&str>) -> std::result::Result<Option<(PathBuf, Option<String>)>, String> {
+	let url = url.trim();
+	let (url, absolute) = if let Some(rest) = url_stub.and_then(|url_stub| url.strip_prefix(url_stub.trim_end_matches('/'))) {
+		if !(rest.is_empty() || rest.starts_with(['/', '?', '#'])) {
+			return Ok(None)
+		}
+		(rest, true)
+	} else if url.is_empty() || has_scheme(url) {
+		return Ok(None)
+	} else {
+		(url, url.starts_with('/'))
+	};
+
+	let (url, fragment) = url.split_once('#').map_or((url, None), |(url, fragment)| (url, Some(fragment)));
+	let path = url.split('?').next().unwrap_or_default();
+	let path = decode(path).map_err(|_| "it isn't a valid URL".to_string())?;
+
+	let mut target = if absolute {
+		PathBuf::new()
+	} else if path.is_empty() {
+		page.to_path_buf()
+	} else {
+		page.parent().map(Path::to_path_buf).unwrap_or_default()
+	};
+	for component in Path::new(&path).components() {
+		match component {
+			Component::Normal(part) => target.push(part),
+			Component::ParentDir if !target.pop() => return Err("it points outside the output directory".to_string()),
+			_ => (),
+		}
+	}
+
+	let full = output_dir.join(&target);
+	if full.is_dir() {
+		target.push("index.html");
+	} else if !full.exists() && full.extension().is_none() && full.with_extension("html").is_file() {
+		target.set_extension("html");
+	}
+	if !output_dir.join(&target).exists() {
+		return Err(["there's no ", &output_dir.join(&target).to_string_lossy()].concat())
+	}
+	let fragment = fragment.map(|fragment| decode(fragment).unwrap_or_else(|_| fragment.to_string()));
+	Ok(Some((target, fragment)))
+}
+
+// Every HTML file in the output directory. Hidden files and directories, like the build cache, are skipped.
+fn html_files(output_dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+	let dir = output_dir.join(relative);
+	for entry in fs::read_dir(&dir).map_err(Error::io(&dir))? {
+		let entry = entry.map_err(Error::io(&dir))?;
+		let name = entry.file_name().to_string_lossy().to_string();
+		if name.starts_with('.') {
+			continue
+		}
+		let path = relative.join(&name);
+		if entry.path().is_dir() {
+			html_files(output_dir, &path, files)?;
+		} else if path.extension().is_some_and(|extension| extension == "html" || extension == "htm") {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
+
+// The line in a page's source that a link was written on, searching from the line after `from` so that repeated links are found in order. This only finds links that are written in the source exactly as they're output, like ordinary Markdown and HTML links. Links added by wiki links, shortcodes, templates or plugins, or whose URL is changed on the way, like by percent-encoding, aren't found.
+fn source_line(source: &str, url: &str, from: usize) -> Option<usize> {
+	source.lines().enumerate().skip(from).find(|(_, line)| line.contains(url)).map(|(index, _)| index + 1)
+}
+
+/// Checks every link in the HTML files in the output directory.
+///
+/// `sources` maps pages built from input files, relative to the output directory, to those files. Broken links in them are reported against the input file, starting with the line the link was written on if its URL is written there as it is in the output, like in ordinary Markdown and HTML links. Otherwise, including for links from wiki links, shortcodes, templates and plugins, and in pages that weren't built from an input file, they start with the byte offset in the output file, like `byte 120 of output/index.html`.
+///
+/// Returns an [`Error::File`] holding [`Error::BrokenLinks`] for every page with broken links.
+///
+/// # Errors
+///
+/// Returns an error if the output directory can't be read.
+pub fn check_links(output_dir: &Path, url_stub: Option<&str>, sources: &BTreeMap<PathBuf, PathBuf>) -> Result<Vec<Error>> {
+	let mut files = Vec::new();
+	html_files(output_dir, Path::new(""), &mut files)?;
+	files.sort();
+	let documents: HashMap<&Path, Document> = files.par_iter().map(|file| {
+		let path = output_dir.join(file);
+		let html = fs::read(&path).map_err(Error::io(&path))?;
+		Ok((file.as_path(), parse(&String::from_utf8_lossy(&html))))
+	}).collect::<Result<_>>()?;
+
+	files.iter().filter_map(|file| {
+		let document = documents.get(file.as_path())?;
+		let broken: Vec<(&Reference, String)> = document.references.iter().filter_map(|reference| {
+			let message = match resolve(&reference.url, file, output_dir, url_stub) {
+				Ok(Some((target, Some(fragment)))) => {
+					// An empty fragment or `#top` always scrolls to the top of the page.
+					let ids = documents.get(target.as_path()).map(|target| &target.ids);
+					if fragment.is_empty() || fragment.eq_ignore_ascii_case("top") || ids.is_none_or(|ids| ids.contains(&fragment)) {
+						return None
+					}
+					["there's no #", &fragment, " in ", &output_dir.join(&target).to_string_lossy()].concat()
+				},
+				Ok(_) => return None,
+				Err(reason) => reason,
+			};
+			Some((reference, message))
+		}).collect();
+		if broken.is_empty() {
+			return None
+		}
+
+		let output = output_dir.join(file);
+		let (path, source) = match sources.get(file) {
+			Some(input) => match fs::read_to_string(input) {
+				Ok(source) => (input.to_owned(), Some(source)),
+				Err(err) => return Some(Err(Error::io(input)(err))),
+			},
+			None => (output.to_owned(), None),
+		};
+		let mut searched: HashMap<&str, usize> = HashMap::new();
+		let messages = broken.into_iter().map(|(reference, message)| {
+			let from = searched.get(reference.url.as_str()).copied().unwrap_or(0);
+			let line = source.as_deref().and_then(|source| source_line(source, &reference.url, from));
+			let location = line.map_or_else(|| format!("byte {} of {}", reference.offset, output.to_string_lossy()), |line| {
+				searched.insert(&reference.url, line);
+				format!("line {}", line)
+			});
+			format!("{}: {:#?} is broken, {}", location, reference.url, message)
+		}).collect();
+		Some(Ok(Error::File {
+			path,
+			source: Box::new(Error::BrokenLinks(messages)),
+		}))
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn references_are_found_by_offset() {
+		let document = parse("<p id=intro>Hi</p><a href=\"/a.html\">a</a><img srcset=\"b.png 1x, c.png 2x\"><script>\"<a href=x>\"</script>");
+		let references: Vec<(usize, &str)> = document.references.iter().map(|reference| (reference.offset, reference.url.as_str())).collect();
+		assert_eq!(references, [(18, "/a.html"), (41, "b.png"), (41, "c.png")]);
+		assert!(document.ids.contains("intro"));
+	}
+
+	#[test]
+	fn repeated_links_are_found_on_their_own_lines() {
+		let source = "+++\ntitle = \"x\"\n+++\n[a](missing.html)\n\ntext\n[b](missing.html)\n";
+		assert_eq!(source_line(source, "missing.html", 0), Some(4));
+		assert_eq!(source_line(source, "missing.html", 4), Some(7));
+		assert_eq!(source_line(source, "missing.html", 7), None);
+		assert_eq!(source_line(source, "/nav.html", 0), None);
+	}
+
+	#[test]
+	fn links_missing_from_the_source_are_reported_by_output_offset() {
+		let site = std::env::temp_dir().join(format!("katsite-test-{}-linkcheck", std::process::id()));
+		fs::create_dir_all(site.join("output")).unwrap();
+		fs::write(site.join("index.md"), "[a](missing.html)\n[[Wiki link]]\n").unwrap();
+		fs::write(site.join("output/index.html"), "<a href=\"missing.html\">a</a><a href=\"wiki%20link.html\">Wiki link</a>").unwrap();
+		fs::write(site.join("output/other.html"), "<a href=\"missing.html\">a</a>").unwrap();
+		let output = site.join("output");
+		let sources = BTreeMap::from([(PathBuf::from("index.html"), site.join("index.md"))]);
+		let mut locations: Vec<Vec<String>> = check_links(&output, None, &sources).unwrap().into_iter().map(|err| match err {
+			Error::File { path, source } => match *source {
+				Error::BrokenLinks(messages) => messages.iter().map(|message| [&path.to_string_lossy(), ": ", message.split(':').next().unwrap_or_default()].concat()).collect(),
+				source => panic!("expected broken links, got {:?}", source),
+			},
+			err => panic!("expected broken links, got {:?}", err),
+		}).collect();
+		locations.sort();
+		let path = |file: &str| site.join(file).to_string_lossy().to_string();
+		assert_eq!(locations, [
+			vec![format!("{}: line 1", path("index.md")), format!("{}: byte 28 of {}", path("index.md"), path("output/index.html"))],
+			vec![format!("{}: byte 0 of {}", path("output/other.html"), path("output/other.html"))],
+		]);
+		fs::remove_dir_all(site).unwrap();
+	}
+}
@@ -0,0 +1,231 @@
+//! Wiki-style links between pages, like `[[Page Title]]` or `[[file-stem#heading|label]]`.
+//!
+//! Links are resolved against every input file by path (like `docs/intro`), file stem, or frontmatter title, ignoring case. Before the Markdown is rendered, they're replaced with ordinary links to the page's output file, relative to the page they're in. Headings are turned into ids the same way that header ids are, so `[[intro#Getting started]]` links to `#getting-started`, and `[[#heading]]` links within the same page.
+//!
+//! Links without a `|label` are labelled with the page's title (or the link's target, if the page has no title), followed by the heading if there is one, like `Introduction > Getting started`.
+
+use crate::{config::Config, error::{Error, Result}, frontmatter, markdown::{closing_backticks, fenced_code_end}, site::{inside_site, relative_path}, toc::slug};
+use std::{collections::HashMap, fs, hash::Hasher, path::{Path, PathBuf}};
+use urlencoding::encode;
+
+/// Every input file that links can point to.
+#[derive(Default)]
+pub struct Pages {
+	// Lowercased paths without extensions, file stems and titles, mapped to the input files they could mean.
+	paths: HashMap<String, Vec<PathBuf>>,
+	stems: HashMap<String, Vec<PathBuf>>,
+	titles: HashMap<String, Vec<PathBuf>>,
+	// The title of every input file that has one, for labelling links to it.
+	labels: HashMap<PathBuf, String>,
+}
+
+fn add(map: &mut HashMap<String, Vec<PathBuf>>, key: &str, path: &Path) {
+	let paths = map.entry(key.to_lowercase()).or_default();
+	if !paths.iter().any(|existing| existing == path) {
+		paths.push(path.to_path_buf());
+	}
+}
+
+fn escape_label(label: &str) -> String {
+	label.replace('\\', "\\\\").replace('[', "\\[").replace(']', "\\]")
+}
+
+impl Pages {
+	/// Finds every published input file, and reads their titles.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the input glob is invalid.
+	pub fn load(config: &Config) -> Result<Self> {
+		let mut pages = Self::default();
+		for path in glob::glob(&config.files.input_glob)?.filter_map(std::result::Result::ok).filter(|path| inside_site(path)) {
+			// Invalid frontmatter is reported when the page itself is built.
+			let frontmatter = fs::read_to_string(&path).ok().and_then(|input| frontmatter::parse(&input).ok());
+			// Links to pages that aren't published would be broken.
+			if frontmatter.as_ref().is_some_and(|frontmatter| !matches!(frontmatter::unpublished(frontmatter, &config.files), Ok(None))) {
+				continue
+			}
+			let title = frontmatter.as_ref().and_then(|frontmatter| frontmatter.get("title")).and_then(toml::Value::as_str);
+			pages.insert(&relative_path(&path), title);
+		}
+		Ok(pages)
+	}
+
+	fn insert(&mut self, path: &Path, title: Option<&str>) {
+		add(&mut self.paths, &path.with_extension("").to_string_lossy().replace('\\', "/"), path);
+		if let Some(stem) = path.file_stem() {
+			add(&mut self.stems, &stem.to_string_lossy(), path);
+		}
+		if let Some(title) = title {
+			add(&mut self.titles, title, path);
+			self.labels.insert(path.to_path_buf(), title.to_string());
+		}
+	}
+
+	/// Hashes every page that links can point to, so that pages are rebuilt when a page they may link to is renamed.
+	pub fn hash_into<H: Hasher>(&self, hasher: &mut H) {
+		for map in &[&self.paths, &self.stems, &self.titles] {
+			let mut entries: Vec<_> = map.iter().collect();
+			entries.sort();
+			for (key, paths) in entries {
+				hasher.write(key.as_bytes());
+				for path in paths {
+					hasher.write(path.to_string_lossy().as_bytes());
+				}
+			}
+		}
+	}
+
+	// Finds the input file that a link points to.
+	fn resolve(&self, target: &str) -> std::result::Result<&Path, String> {
+		let key = target.trim_start_matches("./").trim_end_matches(".md").to_lowercase();
+		let candidates = [&self.paths, &self.stems, &self.titles].iter().find_map(|map| map.get(&key));
+		match candidates.map(Vec::as_slice) {
+			Some([path]) => Ok(path),
+			Some(paths) => Err(format!("[[{}]] is ambiguous, it could link to {}. Use the file's path instead, like [[{}]].", target, paths.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>().join(" or "), paths[0].with_extension("").to_string_lossy())),
+			None => Err(format!("[[{}]] doesn't match the path, file name or title of any page", target)),
+		}
+	}
+
+	// The Markdown link that a wiki link is replaced with.
+	fn link(&self, link: &str, page: &Path, config: &Config) -> std::result::Result<String, String> {
+		let (target, label) = link.split_once('|').map_or((link, None), |(target, label)| (target, Some(label.trim())));
+		let (target, heading) = target.split_once('#').map_or((target, None), |(target, heading)| (target, Some(heading.trim())));
+		let target = target.trim();
+
+		let (mut url, title) = if target.is_empty() {
+			(String::new(), None)
+		} else {
+			let resolved = self.resolve(target)?;
+			let depth = relative_path(page).components().count().saturating_sub(1);
+			let output = relative_path(resolved).with_extension("html");
+			let segments: Vec<String> = output.components().map(|segment| encode(&segment.as_os_str().to_string_lossy())).collect();
+			(["../".repeat(depth), segments.join("/")].concat(), Some(self.labels.get(resolved).map_or(target, String::as_str)))
+		};
+		let label = label.map_or_else(|| match (title, heading) {
+			(Some(title), Some(heading)) => [title, " > ", heading].concat(),
+			(Some(title), None) => title.to_string(),
+			(None, heading) => heading.unwrap_or_default().to_string(),
+		}, str::to_string);
+		if let Some(heading) = heading {
+			url = [url, "#".to_string(), encode(&[config.markdown.header_id_prefix.as_str(), &slug(heading)].concat())].concat();
+		}
+		Ok(["[", &escape_label(&label), "](", &url, ")"].concat())
+	}
+
+	/// Replaces every wiki link in a Markdown file with a link to the page it points to. Links in code are left alone.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Links`] listing every link that doesn't match exactly one page, along with the line it's on.
+	pub fn replace_links(&self, input: &str, page: &Path, config: &Config) -> Result<String> {
+		if !input.contains("[[") {
+			return Ok(input.to_string())
+		}
+		let (frontmatter, body) = frontmatter::split(input);
+		let lines_before = frontmatter.matches('\n').count();
+		let bytes = body.as_bytes();
+
+		let mut output = frontmatter.to_string();
+		let mut errors = Vec::new();
+		let mut copied = 0;
+		let mut position = 0;
+		let mut line_start = true;
+		while position < bytes.len() {
+			if line_start {
+				line_start = false;
+				if let Some(end) = fenced_code_end(body, position) {
+					position = end;
+					line_start = true;
+					continue
+				}
+			}
+			match bytes[position] {
+				b'\n' => {
+					line_start = true;
+					position += 1;
+				},
+				b'\\' if bytes.get(position + 1) != Some(&b'\n') => position += 2,
+				b'`' => {
+					let length = bytes[position..].iter().take_while(|byte| **byte == b'`').count();
+					position = closing_backticks(bytes, position + length, length).map_or(position, |end| end) + length;
+				},
+				b'[' if bytes.get(position + 1) == Some(&b'[') => {
+					let start = position + 2;
+					let end = body[start..].find([']', '[', '\n']).map(|end| start + end).filter(|end| body[*end..].starts_with("]]"));
+					let end = if let Some(end) = end.filter(|end| *end > start) {
+						end
+					} else {
+						position += 2;
+						continue
+					};
+					output.push_str(&body[copied..position]);
+					match self.link(&body[start..end], page, config) {
+						Ok(link) => output.push_str(&link),
+						Err(message) => {
+							errors.push(format!("line {}: {}", lines_before + body[..position].matches('\n').count() + 1, message));
+							output.push_str(&body[position..end + 2]);
+						},
+					}
+					position = end + 2;
+					copied = position;
+				},
+				_ => position += 1,
+			}
+		}
+		output.push_str(&body[copied..]);
+
+		if errors.is_empty() {
+			Ok(output)
+		} else {
+			Err(Error::Links(errors))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pages() -> Pages {
+		let mut pages = Pages::default();
+		pages.insert(Path::new("index.md"), Some("Home"));
+		pages.insert(Path::new("docs/intro.md"), Some("Introduction"));
+		pages.insert(Path::new("docs/setup.md"), None);
+		pages.insert(Path::new("notes/setup.md"), None);
+		pages
+	}
+
+	fn replace(input: &str) -> Result<String> {
+		pages().replace_links(input, Path::new("docs/guide.md"), &Config::default())
+	}
+
+	#[test]
+	fn links_resolve_by_title_stem_or_path() {
+		for link in &["[[Introduction]]", "[[intro]]", "[[docs/intro]]", "[[./docs/intro.md]]", "[[ INTRO ]]"] {
+			assert_eq!(replace(link).unwrap(), "[Introduction](../docs/intro.html)", "{}", link);
+		}
+		assert_eq!(replace("[[home]]").unwrap(), "[Home](../index.html)");
+	}
+
+	#[test]
+	fn labels_default_to_the_title_and_heading() {
+		assert_eq!(replace("[[intro#Getting started]]").unwrap(), "[Introduction > Getting started](../docs/intro.html#getting-started)");
+		assert_eq!(replace("[[intro#Getting started|Start here]]").unwrap(), "[Start here](../docs/intro.html#getting-started)");
+		assert_eq!(replace("[[#Getting started]]").unwrap(), "[Getting started](#getting-started)");
+		// Pages without a title are labelled with the link's target.
+		assert_eq!(replace("[[docs/setup]]").unwrap(), "[docs/setup](../docs/setup.html)");
+	}
+
+	#[test]
+	fn unresolved_links_are_errors() {
+		let errors = match replace("# Guide\n\nSee [[missing]] and [[setup]].\n") {
+			Err(Error::Links(errors)) => errors,
+			result => panic!("expected link errors, got {:?}", result),
+		};
+		assert_eq!(errors, vec![
+			"line 3: [[missing]] doesn't match the path, file name or title of any page".to_string(),
+			"line 3: [[setup]] is ambiguous, it could link to docs/setup.md or notes/setup.md. Use the file's path instead, like [[docs/setup]].".to_string(),
+		]);
+	}
+}
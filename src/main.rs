@@ -3,204 +3,151 @@
 #![warn(clippy::cargo)]
 #![allow(clippy::cargo_common_metadata)]
 #![allow(clippy::multiple_crate_versions)]
+#![allow(clippy::uninlined_format_args)]
+#![allow(clippy::unnecessary_debug_formatting)]
+#![allow(clippy::implicit_clone)]
+#![allow(clippy::manual_let_else)]
+#![allow(clippy::struct_excessive_bools)]
 #![warn(clippy::all)]
 
-use comrak::{Arena, parse_document, format_html, ComrakOptions, ComrakExtensionOptions, ComrakParseOptions, ComrakRenderOptions};
-use glob::glob;
-use pulldown_cmark::{Parser, html};
-use rayon::prelude::*;
-use serde_derive::Deserialize;
-use std::{thread, string::String, path::PathBuf, fs, fs::File, io::{Error, Read, Write, BufWriter}, process::{exit, Command, Stdio}};
-
-#[derive(Deserialize)]
-struct Config {
-	plugins_list: Vec<String>,
-	files: Files,
-	markdown: Markdown,
-}
-
-#[derive(Deserialize)]
-struct Files {
-	input_glob: String,
-	output_dir: PathBuf,
-}
-
-#[derive(Deserialize)]
-struct Markdown {
-	github_extensions: bool,
-	comrak_extensions: bool,
-}
-
-const DEFAULT_CONFIG: &str = "plugins_list = []
-[files]
-input_glob = \"./*.md\"
-output_dir = \".\"
-[markdown]
-github_extensions = false
-comrak_extensions = false";
-
-fn init_plugin(hook: &str, plugin: &str) {
-	let mut child = Command::new(PathBuf::from("plugins/").join(plugin))
-		.arg(hook)
-		.stdin(Stdio::null())
-		.stdout(Stdio::inherit())
-		.stderr(Stdio::inherit())
-		.spawn().unwrap_or_else(|err| {
-			eprintln!("Unable to start plugin {}! Additional info below:\n{}", plugin, err);
-			exit(exitcode::UNAVAILABLE);
-		});
-	let _ = child.wait();
+use katsite::{config::CONFIG_FILE, serve::serve, watch::watch, Builder, Config, Error, Site, Verbosity};
+use std::{path::{Path, PathBuf}, process::exit};
+use structopt::StructOpt;
+
+/// A minimal and flexible site generator using Markdown.
+#[derive(StructOpt)]
+#[structopt(name = "katsite")]
+struct Options {
+	/// Config file to load.
+	#[structopt(short, long, global = true, default_value = CONFIG_FILE, parse(from_os_str))]
+	config: PathBuf,
+	/// Override the output directory.
+	#[structopt(short, long, global = true, parse(from_os_str))]
+	output: Option<PathBuf>,
+	/// Override the pattern used to find input files.
+	#[structopt(long, global = true)]
+	input_glob: Option<String>,
+	/// Override a config setting, like `--set files.incremental=false`. Can be given multiple times.
+	#[structopt(long = "set", global = true, number_of_values = 1)]
+	overrides: Vec<String>,
+	/// Build drafts too, for previewing them locally.
+	#[structopt(long, global = true)]
+	drafts: bool,
+	/// Build pages with a publish date in the future too, for previewing them locally.
+	#[structopt(long, global = true)]
+	future: bool,
+	/// Only print warnings and errors.
+	#[structopt(short, long, global = true, conflicts_with = "verbose")]
+	quiet: bool,
+	/// Also print files that are skipped.
+	#[structopt(short, long, global = true)]
+	verbose: bool,
+	#[structopt(subcommand)]
+	command: Option<Command>,
 }
 
-fn init_plugins(hook: String, list: Vec<String>, ordered: bool) -> thread::JoinHandle<()> {
-	thread::spawn(move || {
-		if ordered {
-			list.iter().for_each(|plugin| {
-				init_plugin(&hook, plugin)
-			});
-		} else {
-			list.par_iter().for_each(|plugin| {
-				init_plugin(&hook, plugin)
-			});
-		}
-	})
+#[derive(StructOpt)]
+enum Command {
+	/// Build the site (the default).
+	Build,
+	/// Check the config, input files and plugins for problems, without building anything.
+	Check,
+	/// Check the links in the built site, without building it.
+	CheckLinks,
+	/// Remove everything that a build creates.
+	Clean,
+	/// Build the site, then rebuild it on changes.
+	Watch,
+	/// Build the site, then serve it locally, rebuilding and reloading it on changes.
+	Serve,
 }
 
-fn run_plugins(mut buffer: &mut Vec<u8>, hook: &str, filename: &str, list: &[String]) {
-	for plugin in list {
-		let mut child = Command::new(PathBuf::from("plugins/").join(plugin))
-			.arg(hook)
-			.arg(filename)
-			.stdin(Stdio::piped())
-			.stdout(Stdio::piped())
-			.stderr(Stdio::inherit())
-			.spawn().unwrap_or_else(|err| {
-				eprintln!("Unable to start plugin {}! Additional info below:\n{}", plugin, err);
-				exit(exitcode::UNAVAILABLE);
-			});
-
-		child.stdin.as_mut().unwrap()
-			.write_all(buffer).unwrap_or_else(|_| {
-				eprintln!("Plugin {} crashed during usage!", plugin);
-				exit(exitcode::UNAVAILABLE);
-			});
-
-		buffer.clear();
-		drop(child.stdin.take());
-
-		child.stdout.as_mut().unwrap()
-			.read_to_end(&mut buffer).unwrap_or_else(|_| {
-				eprintln!("Plugin {} crashed during usage!", plugin);
-				exit(exitcode::UNAVAILABLE);
-			});
-
-		let _ = child.kill();
-	}
-}
-
-fn markdown_to_html(input: &str, output: &mut dyn Write, github_ext: bool, comrak_ext: bool) -> Result<(), Error> {
-	if github_ext || comrak_ext {
-		let arena = &Arena::new();
-		let options = &ComrakOptions {
-			extension: ComrakExtensionOptions {
-				strikethrough: github_ext,
-				tagfilter: false, // A proper HTML sanitizer should be used instead.
-				table: github_ext,
-				autolink: github_ext,
-				tasklist: github_ext,
-				superscript: comrak_ext,
-				header_ids: {
-					if github_ext {
-						Some("".to_string())
-					} else {
-						None
-					}
-				},
-				footnotes: comrak_ext,
-				description_lists: comrak_ext,
-			},
-			parse: ComrakParseOptions {
-				smart: comrak_ext,
-				default_info_string: None,
-			},
-			render: ComrakRenderOptions {
-				hardbreaks: false, // Disabled for consistency with other Markdown parsers.
-				github_pre_lang: false, // Disabled for consistency with other Markdown parsers.
-				width: 0, // Ignored when generating HTML
-				unsafe_: true, // A proper HTML sanitizer should be used instead.
-				escape: false, // A proper HTML sanitizer should be used instead.
-			},
-		};
-		let root = parse_document(arena, input, options);
-		format_html(root, options, output)
+fn load_site(options: &Options) -> Site {
+	let verbosity = if options.quiet {
+		Verbosity::Quiet
+	} else if options.verbose {
+		Verbosity::Verbose
 	} else {
-		let parser = Parser::new(input);
-		html::write_html(output, parser)
+		Verbosity::Normal
+	};
+	if verbosity != Verbosity::Quiet {
+		println!("Loading config...");
 	}
-}
 
-fn parse_to_file(input: &mut Vec<u8>, output: &mut dyn Write, filename: &str, config: &Config) -> Result<(), Error> {
-	let mut output = BufWriter::new(output);
-	if config.plugins_list.is_empty() {
-		output.write_all(b"<!doctype html><meta name=viewport content=\"width=device-width,initial-scale=1\">")?;
-	} else {
-		run_plugins(input, "markdown", filename, &config.plugins_list);
+	let mut builder = Builder::new().verbosity(verbosity).config_path(&options.config);
+	if !options.config.exists() && options.config == Path::new(CONFIG_FILE) {
+		eprintln!("Warn: Unable to read config file!");
+		builder = builder.config(Config::default());
+	}
+	if let Some(output) = &options.output {
+		builder = builder.output_dir(output);
+	}
+	if let Some(input_glob) = &options.input_glob {
+		builder = builder.input_glob(input_glob);
 	}
-	markdown_to_html(
-		&String::from_utf8_lossy(input),
-		&mut output,
-		config.markdown.github_extensions,
-		config.markdown.comrak_extensions
-	)?;
+	if options.drafts {
+		builder = builder.set("files.drafts=true");
+	}
+	if options.future {
+		builder = builder.set("files.future=true");
+	}
+	for setting in &options.overrides {
+		builder = builder.set(setting);
+	}
+	builder.finish().unwrap_or_else(|err| fail(&err))
+}
 
-	output.flush()
+fn fail(err: &Error) -> ! {
+	eprintln!("{}", err);
+	exit(err.exit_code());
 }
 
 fn main() {
-	println!("Loading config...");
-	let config_input = fs::read_to_string("conf.toml").unwrap_or_else(|_| {
-		eprintln!("Warn: Unable to read config file!");
-		DEFAULT_CONFIG.to_string()
-	});
-	let config: Config = toml::from_str(&config_input).unwrap_or_else(|err| {
-		eprintln!("Unable to parse config file! Additional info below:\n{:#?}", err);
-		exit(exitcode::CONFIG);
-	});
-	let files = glob(&config.files.input_glob).unwrap_or_else(|err| {
-		eprintln!("Unable to parse file glob! Additional info below:\n{:#?}", err);
-		exit(exitcode::CONFIG);
-	}).par_bridge();
-
-	if !config.files.output_dir.exists() {
-		fs::create_dir_all(&config.files.output_dir).unwrap_or_else(|_| {
-			eprintln!("Unable to create output directory!");
-			exit(exitcode::CANTCREAT)
-		});
+	let options = Options::from_args();
+	let mut site = load_site(&options);
+
+	match options.command.unwrap_or(Command::Build) {
+		Command::Build => {
+			site.build().unwrap_or_else(|err| fail(&err));
+		},
+		Command::Check => {
+			let problems = site.check().unwrap_or_else(|err| fail(&err));
+			for problem in &problems {
+				eprintln!("{}", problem);
+			}
+			if !problems.is_empty() {
+				eprintln!("Found {} problem(s).", problems.len());
+				exit(exitcode::DATAERR);
+			}
+			println!("No problems found.");
+		},
+		Command::CheckLinks => {
+			let broken = site.check_links().unwrap_or_else(|err| fail(&err));
+			for err in &broken {
+				eprintln!("{}\n", err);
+			}
+			if !broken.is_empty() {
+				eprintln!("Found broken links in {} page(s).", broken.len());
+				exit(exitcode::DATAERR);
+			}
+			println!("No broken links found.");
+		},
+		Command::Clean => {
+			site.clean().unwrap_or_else(|err| fail(&err));
+		},
+		Command::Watch => {
+			if let Err(err) = site.build() {
+				eprintln!("{}", err);
+			}
+			let err = watch(&mut site, &|| ()).unwrap_err();
+			fail(&err);
+		},
+		Command::Serve => {
+			if let Err(err) = site.build() {
+				eprintln!("{}", err);
+			}
+			let err = serve(&mut site).unwrap_err();
+			fail(&err);
+		},
 	}
-
-	let child = init_plugins("asyncinit".to_string(), config.plugins_list.to_owned(), false);
-
-	files.filter_map(Result::ok).for_each(|fpath| {
-		let input_name = fpath.to_string_lossy();
-
-		println!("Parsing {}...", input_name);
-		let mut input = fs::read(&fpath).unwrap_or_else(|_| {
-			eprintln!("Unable to open {:#?}!", &fpath);
-			exit(exitcode::NOINPUT);
-		});
-
-		let output_path = config.files.output_dir.join(fpath.with_extension("html"));
-		let mut output = File::create(&output_path).unwrap_or_else(|_| {
-			eprintln!("Unable to create {:#?}!", &output_path);
-			exit(exitcode::CANTCREAT);
-		});
-
-		parse_to_file(&mut input, &mut output, &input_name, &config).unwrap_or_else(|_| {
-			eprintln!("Unable to finish parsing {:#?}!", &fpath);
-			exit(exitcode::IOERR);
-		});
-	});
-
-	let _ = init_plugins("postinit".to_string(), config.plugins_list, true).join();
-	let _ = child.join();
 }
@@ -0,0 +1,153 @@
+use crate::{config::{Backend, Highlight, Markdown}, error::{Error, Result}, frontmatter, highlight::highlight_html, math, toc::{process_headings, Heading}};
+use comrak::{Arena, parse_document, format_html, ComrakOptions, ComrakExtensionOptions, ComrakParseOptions, ComrakRenderOptions};
+use pulldown_cmark::{Options, Parser, html};
+use std::io::{self, Write};
+
+// Whether a blank line starts right after this newline.
+pub(crate) fn blank_line_after(bytes: &[u8], newline: usize) -> bool {
+	bytes[newline + 1..].iter().take_while(|byte| **byte != b'\n').all(u8::is_ascii_whitespace)
+}
+
+// If a fenced code block starts at this line, where the line after its closing fence starts.
+pub(crate) fn fenced_code_end(input: &str, start: usize) -> Option<usize> {
+	let line = input[start..].lines().next().unwrap_or("");
+	let indent = line.len() - line.trim_start_matches(' ').len();
+	let fence = line.trim_start_matches(' ');
+	let marker = fence.chars().next().filter(|c| *c == '`' || *c == '~')?;
+	let length = fence.chars().take_while(|c| *c == marker).count();
+	if indent > 3 || length < 3 {
+		return None
+	}
+
+	let mut position = start + line.len() + 1;
+	while position < input.len() {
+		let line = input[position..].lines().next().unwrap_or("");
+		let trimmed = line.trim_start_matches(' ');
+		let closing = trimmed.chars().take_while(|c| *c == marker).count();
+		position += line.len() + 1;
+		if line.len() - trimmed.len() <= 3 && closing >= length && trimmed[closing..].trim().is_empty() {
+			return Some(position.min(input.len()))
+		}
+	}
+	Some(input.len())
+}
+
+// Finds the end of the code span that starts at `start` with `length` backticks.
+pub(crate) fn closing_backticks(bytes: &[u8], start: usize, length: usize) -> Option<usize> {
+	let mut position = start;
+	while position < bytes.len() {
+		match bytes[position] {
+			b'\n' if blank_line_after(bytes, position) => return None,
+			b'`' => {
+				let run = bytes[position..].iter().take_while(|byte| **byte == b'`').count();
+				if run == length {
+					return Some(position)
+				}
+				position += run;
+				continue
+			},
+			_ => {},
+		}
+		position += 1;
+	}
+	None
+}
+
+fn comrak_options(options: &Markdown) -> ComrakOptions {
+	let github_ext = options.github_extensions;
+	let comrak_ext = options.comrak_extensions;
+	ComrakOptions {
+		extension: ComrakExtensionOptions {
+			strikethrough: options.strikethrough.unwrap_or(github_ext),
+			tagfilter: options.tagfilter,
+			table: options.table.unwrap_or(github_ext),
+			autolink: options.autolink.unwrap_or(github_ext),
+			tasklist: options.tasklist.unwrap_or(github_ext),
+			superscript: options.superscript.unwrap_or(comrak_ext),
+			header_ids: None, // Added by `process_headings` instead, so that they're the same with either parser
+			footnotes: options.footnotes.unwrap_or(comrak_ext),
+			description_lists: options.description_lists.unwrap_or(comrak_ext),
+		},
+		parse: ComrakParseOptions {
+			smart: options.smart.unwrap_or(comrak_ext),
+			default_info_string: options.default_info_string.to_owned(),
+		},
+		render: ComrakRenderOptions {
+			hardbreaks: options.hardbreaks,
+			github_pre_lang: options.github_pre_lang,
+			width: 0, // Ignored when generating HTML
+			unsafe_: options.unsafe_,
+			escape: options.escape,
+		},
+	}
+}
+
+fn pulldown_options(options: &Markdown) -> Options {
+	let github_ext = options.github_extensions;
+	let comrak_ext = options.comrak_extensions;
+	let mut pulldown = Options::empty();
+	pulldown.set(Options::ENABLE_STRIKETHROUGH, options.strikethrough.unwrap_or(github_ext));
+	pulldown.set(Options::ENABLE_TABLES, options.table.unwrap_or(github_ext));
+	pulldown.set(Options::ENABLE_TASKLISTS, options.tasklist.unwrap_or(github_ext));
+	pulldown.set(Options::ENABLE_FOOTNOTES, options.footnotes.unwrap_or(comrak_ext));
+	pulldown.set(Options::ENABLE_SMART_PUNCTUATION, options.smart.unwrap_or(comrak_ext));
+	pulldown
+}
+
+fn render(input: &str, output: &mut dyn Write, options: &Markdown) -> io::Result<()> {
+	let (_, input) = frontmatter::split(input);
+	match options.backend() {
+		Backend::Comrak => {
+			let arena = &Arena::new();
+			let options = &comrak_options(options);
+			let root = parse_document(arena, input, options);
+			format_html(root, options, output)
+		},
+		Backend::PulldownCmark => {
+			let parser = Parser::new_ext(input, pulldown_options(options));
+			html::write_html(output, parser)
+		},
+	}
+}
+
+/// A rendered Markdown file.
+pub struct Rendered {
+	pub html: String,
+	/// The file's headers, if `toc` is enabled.
+	pub toc: Vec<Heading>,
+}
+
+/// Renders Markdown to HTML, using the parser and extensions picked in `[markdown]`. Frontmatter is left out. Code blocks are highlighted, math is rendered, and headers are given ids, if enabled.
+///
+/// # Errors
+///
+/// Returns an error if math is enabled and any of it is invalid.
+pub fn markdown_to_html(input: &str, options: &Markdown) -> Result<Rendered> {
+	let (input, formulas) = if options.math {
+		math::extract(input).map_err(Error::Math)?
+	} else {
+		(input.to_owned(), Vec::new())
+	};
+
+	let mut html = Vec::new();
+	// Writing to a Vec can't fail.
+	let _ = render(&input, &mut html, options);
+	let mut html = String::from_utf8_lossy(&html).into_owned();
+	if options.highlight != Highlight::None {
+		html = highlight_html(&html, options);
+	}
+	if !formulas.is_empty() {
+		html = math::restore(&html, &formulas);
+	}
+	if !options.header_ids() {
+		return Ok(Rendered {
+			html,
+			toc: Vec::new(),
+		})
+	}
+	let (html, toc) = process_headings(&html, options);
+	Ok(Rendered {
+		html,
+		toc: if options.toc { toc } else { Vec::new() },
+	})
+}
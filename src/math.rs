@@ -0,0 +1,990 @@
+//! Renders TeX math in Markdown to `MathML` while building, so that pages don't need a script to display it.
+//!
+//! Inline math is written as `$...$`, and display math as `$$...$$`. An opening `$` must be followed by a non-space character, and a closing `$` must follow one and not be followed by a digit, so that prices like `$5` are left alone. Math inside code is left alone, and `\$` is a literal dollar sign.
+//!
+//! Only a subset of TeX is supported: letters, numbers and operators, `^` and `_`, groups, Greek letters and common symbols, `\frac`, `\binom`, `\sqrt`, `\left`/`\middle`/`\right`, `\big` and friends, accents, font commands like `\mathbf`, `\text`, `\operatorname`, spacing commands, and the `matrix`, `cases`, `aligned` and `array` environments (and their variants).
+
+use crate::{frontmatter, markdown::{blank_line_after, closing_backticks, fenced_code_end}};
+
+type Parsed<T> = Result<T, String>;
+
+const GREEK: &[(&str, char)] = &[
+	("alpha", 'α'), ("beta", 'β'), ("gamma", 'γ'), ("delta", 'δ'), ("epsilon", 'ϵ'), ("varepsilon", 'ε'), ("zeta", 'ζ'), ("eta", 'η'), ("theta", 'θ'), ("vartheta", 'ϑ'), ("iota", 'ι'), ("kappa", 'κ'), ("lambda", 'λ'), ("mu", 'μ'), ("nu", 'ν'), ("xi", 'ξ'), ("omicron", 'ο'), ("pi", 'π'), ("varpi", 'ϖ'), ("rho", 'ρ'), ("varrho", 'ϱ'), ("sigma", 'σ'), ("varsigma", 'ς'), ("tau", 'τ'), ("upsilon", 'υ'), ("phi", 'ϕ'), ("varphi", 'φ'), ("chi", 'χ'), ("psi", 'ψ'), ("omega", 'ω'),
+	("Gamma", 'Γ'), ("Delta", 'Δ'), ("Theta", 'Θ'), ("Lambda", 'Λ'), ("Xi", 'Ξ'), ("Pi", 'Π'), ("Sigma", 'Σ'), ("Upsilon", 'Υ'), ("Phi", 'Φ'), ("Psi", 'Ψ'), ("Omega", 'Ω'),
+];
+
+// Symbols that are written as identifiers rather than operators.
+const IDENTIFIERS: &[(&str, char)] = &[
+	("infty", '∞'), ("partial", '∂'), ("nabla", '∇'), ("emptyset", '∅'), ("varnothing", '∅'), ("ell", 'ℓ'), ("hbar", 'ℏ'), ("aleph", 'ℵ'), ("Re", 'ℜ'), ("Im", 'ℑ'), ("wp", '℘'), ("imath", 'ı'), ("jmath", 'ȷ'),
+];
+
+const OPERATORS: &[(&str, char)] = &[
+	("times", '×'), ("cdot", '⋅'), ("div", '÷'), ("pm", '±'), ("mp", '∓'), ("ast", '∗'), ("star", '⋆'), ("circ", '∘'), ("bullet", '∙'), ("oplus", '⊕'), ("ominus", '⊖'), ("otimes", '⊗'), ("odot", '⊙'), ("setminus", '∖'), ("cup", '∪'), ("cap", '∩'), ("wedge", '∧'), ("land", '∧'), ("vee", '∨'), ("lor", '∨'), ("neg", '¬'), ("lnot", '¬'),
+	("leq", '≤'), ("le", '≤'), ("geq", '≥'), ("ge", '≥'), ("neq", '≠'), ("ne", '≠'), ("ll", '≪'), ("gg", '≫'), ("approx", '≈'), ("equiv", '≡'), ("sim", '∼'), ("simeq", '≃'), ("cong", '≅'), ("propto", '∝'), ("doteq", '≐'), ("prec", '≺'), ("succ", '≻'), ("preceq", '⪯'), ("succeq", '⪰'),
+	("in", '∈'), ("notin", '∉'), ("ni", '∋'), ("subset", '⊂'), ("supset", '⊃'), ("subseteq", '⊆'), ("supseteq", '⊇'), ("mid", '∣'), ("parallel", '∥'), ("perp", '⊥'), ("forall", '∀'), ("exists", '∃'), ("nexists", '∄'), ("therefore", '∴'), ("because", '∵'),
+	("to", '→'), ("rightarrow", '→'), ("leftarrow", '←'), ("gets", '←'), ("leftrightarrow", '↔'), ("Rightarrow", '⇒'), ("Leftarrow", '⇐'), ("Leftrightarrow", '⇔'), ("implies", '⟹'), ("impliedby", '⟸'), ("iff", '⟺'), ("mapsto", '↦'), ("longrightarrow", '⟶'), ("longleftarrow", '⟵'), ("uparrow", '↑'), ("downarrow", '↓'),
+	("ldots", '…'), ("dots", '…'), ("cdots", '⋯'), ("vdots", '⋮'), ("ddots", '⋱'), ("angle", '∠'), ("prime", '′'), ("colon", ':'), ("backslash", '\\'),
+	("langle", '⟨'), ("rangle", '⟩'), ("lfloor", '⌊'), ("rfloor", '⌋'), ("lceil", '⌈'), ("rceil", '⌉'), ("vert", '|'), ("lvert", '|'), ("rvert", '|'), ("Vert", '‖'), ("lVert", '‖'), ("rVert", '‖'), ("|", '‖'), ("{", '{'), ("}", '}'),
+	("#", '#'), ("%", '%'), ("&", '&'), ("$", '$'), ("_", '_'),
+];
+
+// Operators whose scripts go above and below them in display math.
+const BIG_OPERATORS: &[(&str, char)] = &[
+	("sum", '∑'), ("prod", '∏'), ("coprod", '∐'), ("bigcup", '⋃'), ("bigcap", '⋂'), ("bigvee", '⋁'), ("bigwedge", '⋀'), ("bigoplus", '⨁'), ("bigotimes", '⨂'), ("bigodot", '⨀'), ("biguplus", '⨄'),
+];
+
+const INTEGRALS: &[(&str, char)] = &[("int", '∫'), ("iint", '∬'), ("iiint", '∭'), ("oint", '∮')];
+
+const FUNCTIONS: &[&str] = &[
+	"sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh", "coth", "log", "ln", "lg", "exp", "deg", "dim", "ker", "arg", "hom",
+];
+
+// Functions whose scripts go below them in display math.
+const LIMIT_FUNCTIONS: &[(&str, &str)] = &[
+	("lim", "lim"), ("limsup", "lim sup"), ("liminf", "lim inf"), ("max", "max"), ("min", "min"), ("sup", "sup"), ("inf", "inf"), ("det", "det"), ("gcd", "gcd"), ("Pr", "Pr"),
+];
+
+// Accents, and whether they stretch over their whole argument.
+const ACCENTS: &[(&str, char, bool)] = &[
+	("hat", '^', false), ("widehat", '^', true), ("check", 'ˇ', false), ("tilde", '~', false), ("widetilde", '~', true), ("acute", '´', false), ("grave", '`', false), ("dot", '˙', false), ("ddot", '¨', false), ("breve", '˘', false), ("bar", '¯', false), ("vec", '→', false),
+	("overline", '‾', true), ("overrightarrow", '→', true), ("overleftarrow", '←', true),
+];
+
+const SPACES: &[(&str, &str)] = &[
+	(",", "0.1667em"), (":", "0.2222em"), (">", "0.2222em"), (";", "0.2778em"), ("!", "-0.1667em"), (" ", "0.25em"), ("enspace", "0.5em"), ("thinspace", "0.1667em"), ("quad", "1em"), ("qquad", "2em"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Variant {
+	Normal,
+	Bold,
+	Italic,
+	BoldItalic,
+	Script,
+	Fraktur,
+	DoubleStruck,
+	SansSerif,
+	Monospace,
+}
+
+const FONTS: &[(&str, Variant)] = &[
+	("mathrm", Variant::Normal), ("mathbf", Variant::Bold), ("mathit", Variant::Italic), ("boldsymbol", Variant::BoldItalic), ("bm", Variant::BoldItalic), ("mathcal", Variant::Script), ("mathscr", Variant::Script), ("mathfrak", Variant::Fraktur), ("mathbb", Variant::DoubleStruck), ("mathsf", Variant::SansSerif), ("mathtt", Variant::Monospace),
+];
+
+fn lookup<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+	table.iter().find(|(entry, _)| *entry == name).map(|(_, value)| *value)
+}
+
+// Maps a letter or digit to its Unicode mathematical alphanumeric symbol, which displays correctly in every browser.
+fn styled(c: char, variant: Variant) -> char {
+	let exception = match (variant, c) {
+		(Variant::Italic, 'h') => Some('ℎ'),
+		(Variant::Script, 'B') => Some('ℬ'),
+		(Variant::Script, 'E') => Some('ℰ'),
+		(Variant::Script, 'F') => Some('ℱ'),
+		(Variant::Script, 'H') => Some('ℋ'),
+		(Variant::Script, 'I') => Some('ℐ'),
+		(Variant::Script, 'L') => Some('ℒ'),
+		(Variant::Script, 'M') => Some('ℳ'),
+		(Variant::Script, 'R') => Some('ℛ'),
+		(Variant::Script, 'e') => Some('ℯ'),
+		(Variant::Script, 'g') => Some('ℊ'),
+		(Variant::Script, 'o') => Some('ℴ'),
+		(Variant::Fraktur, 'C') => Some('ℭ'),
+		(Variant::Fraktur, 'H') => Some('ℌ'),
+		(Variant::Fraktur, 'I') => Some('ℑ'),
+		(Variant::Fraktur, 'R') => Some('ℜ'),
+		(Variant::Fraktur, 'Z') => Some('ℨ'),
+		(Variant::DoubleStruck, 'C') => Some('ℂ'),
+		(Variant::DoubleStruck, 'H') => Some('ℍ'),
+		(Variant::DoubleStruck, 'N') => Some('ℕ'),
+		(Variant::DoubleStruck, 'P') => Some('ℙ'),
+		(Variant::DoubleStruck, 'Q') => Some('ℚ'),
+		(Variant::DoubleStruck, 'R') => Some('ℝ'),
+		(Variant::DoubleStruck, 'Z') => Some('ℤ'),
+		_ => None,
+	};
+	if let Some(exception) = exception {
+		return exception
+	}
+
+	let (upper, lower, digits) = match variant {
+		Variant::Normal => return c,
+		Variant::Bold => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+		Variant::Italic => (0x1D434, 0x1D44E, None),
+		Variant::BoldItalic => (0x1D468, 0x1D482, Some(0x1D7CE)),
+		Variant::Script => (0x1D49C, 0x1D4B6, None),
+		Variant::Fraktur => (0x1D504, 0x1D51E, None),
+		Variant::DoubleStruck => (0x1D538, 0x1D552, Some(0x1D7D8)),
+		Variant::SansSerif => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+		Variant::Monospace => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+	};
+	let code = match c {
+		'A'..='Z' => Some(upper + (c as u32 - 'A' as u32)),
+		'a'..='z' => Some(lower + (c as u32 - 'a' as u32)),
+		'0'..='9' => digits.map(|digits| digits + (c as u32 - '0' as u32)),
+		_ => None,
+	};
+	code.and_then(std::char::from_u32).unwrap_or(c)
+}
+
+fn escape(input: &str) -> String {
+	input.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+fn operator(c: char) -> String {
+	// Delimiters only stretch when they're used with \left and \right, like in TeX.
+	if "()[]{}|‖⟨⟩⌊⌋⌈⌉".contains(c) {
+		format!("<mo stretchy=\"false\">{}</mo>", escape(&c.to_string()))
+	} else {
+		format!("<mo>{}</mo>", escape(&c.to_string()))
+	}
+}
+
+fn row(items: &[String]) -> String {
+	if items.len() == 1 {
+		items.concat()
+	} else {
+		["<mrow>", &items.concat(), "</mrow>"].concat()
+	}
+}
+
+fn fence(delimiter: &str) -> String {
+	if delimiter.is_empty() {
+		String::new()
+	} else {
+		format!("<mo fence=\"true\">{}</mo>", escape(delimiter))
+	}
+}
+
+struct Atom {
+	mathml: String,
+	// Whether scripts go above and below it in display math.
+	limits: bool,
+}
+
+impl Atom {
+	const fn new(mathml: String) -> Self {
+		Self {
+			mathml,
+			limits: false,
+		}
+	}
+}
+
+// A command that doesn't take any arguments.
+fn symbol(name: &str) -> Option<Atom> {
+	let mathml = if let Some(letter) = lookup(GREEK, name) {
+		if letter.is_uppercase() {
+			format!("<mi mathvariant=\"normal\">{}</mi>", letter)
+		} else {
+			format!("<mi>{}</mi>", letter)
+		}
+	} else if let Some(symbol) = lookup(IDENTIFIERS, name) {
+		format!("<mi>{}</mi>", symbol)
+	} else if let Some(symbol) = lookup(OPERATORS, name) {
+		operator(symbol)
+	} else if let Some(symbol) = lookup(BIG_OPERATORS, name) {
+		return Some(Atom {
+			mathml: format!("<mo>{}</mo>", symbol),
+			limits: true,
+		})
+	} else if let Some(symbol) = lookup(INTEGRALS, name) {
+		format!("<mo>{}</mo>", symbol)
+	} else if FUNCTIONS.contains(&name) {
+		format!("<mi>{}</mi>", name)
+	} else if let Some(function) = lookup(LIMIT_FUNCTIONS, name) {
+		return Some(Atom {
+			mathml: format!("<mi>{}</mi>", function),
+			limits: true,
+		})
+	} else if let Some(width) = lookup(SPACES, name) {
+		format!("<mspace width=\"{}\"/>", width)
+	} else {
+		return None
+	};
+	Some(Atom::new(mathml))
+}
+
+// How deeply groups, commands and environments can be nested, which keeps deeply nested input from overflowing the stack.
+const MAX_NESTING: usize = 100;
+
+struct Parser {
+	chars: Vec<char>,
+	position: usize,
+	display: bool,
+	variant: Option<Variant>,
+	// How many atoms are being parsed, including the current one. Every group, command argument and environment is inside of an atom.
+	nesting: usize,
+}
+
+impl Parser {
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.position).copied()
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self.peek().is_some_and(char::is_whitespace) {
+			self.position += 1;
+		}
+	}
+
+	// The name of the command at the current position, without consuming it.
+	fn peek_command(&self) -> Option<String> {
+		if self.peek() != Some('\\') {
+			return None
+		}
+		let rest = &self.chars[self.position + 1..];
+		let letters: String = rest.iter().take_while(|c| c.is_ascii_alphabetic()).collect();
+		if letters.is_empty() {
+			rest.first().map(char::to_string)
+		} else {
+			Some(letters)
+		}
+	}
+
+	fn eat_command(&mut self, name: &str) -> bool {
+		if self.peek_command().as_deref() == Some(name) {
+			self.position += 1 + name.chars().count();
+			true
+		} else {
+			false
+		}
+	}
+
+	// Whether the current row ends here.
+	fn at_stop(&self, brackets: bool) -> bool {
+		match self.peek() {
+			None | Some('}' | '&') => true,
+			Some(']') => brackets,
+			Some('\\') => matches!(self.peek_command().as_deref(), Some("\\" | "right" | "middle" | "end")),
+			_ => false,
+		}
+	}
+
+	// Describes whatever ended a row too early.
+	fn stray(&self) -> String {
+		match self.peek() {
+			None => "the math ends too early".to_string(),
+			Some('}') => "unexpected }".to_string(),
+			Some(']') => "unexpected ]".to_string(),
+			Some('&') => "& is only allowed inside environments like aligned and matrix".to_string(),
+			_ => match self.peek_command().as_deref() {
+				Some("\\") => "\\\\ is only allowed inside environments like aligned and matrix".to_string(),
+				Some("right") => "\\right without a matching \\left".to_string(),
+				Some("middle") => "\\middle without a matching \\left".to_string(),
+				Some("end") => "\\end without a matching \\begin".to_string(),
+				_ => "unexpected input".to_string(),
+			},
+		}
+	}
+
+	fn expect(&mut self, close: char) -> Parsed<()> {
+		self.skip_whitespace();
+		match self.peek() {
+			Some(c) if c == close => {
+				self.position += 1;
+				Ok(())
+			},
+			None => Err(format!("missing {}", close)),
+			_ => Err(self.stray()),
+		}
+	}
+
+	fn parse_row(&mut self, brackets: bool) -> Parsed<String> {
+		let mut items = Vec::new();
+		loop {
+			self.skip_whitespace();
+			if self.at_stop(brackets) {
+				break
+			}
+			items.push(self.parse_scripted()?);
+		}
+		Ok(row(&items))
+	}
+
+	fn parse_group(&mut self) -> Parsed<String> {
+		self.position += 1;
+		let row = self.parse_row(false)?;
+		self.expect('}')?;
+		Ok(row)
+	}
+
+	// The argument of a command or script, which is either a group or a single token.
+	fn parse_argument(&mut self, command: &str) -> Parsed<String> {
+		self.skip_whitespace();
+		if self.peek() != Some('{') && self.at_stop(false) {
+			return Err(format!("{} is missing an argument", command))
+		}
+		self.parse_atom(true).map(|atom| atom.mathml)
+	}
+
+	// The raw text argument of a command, like `\text{...}` or `\begin{...}`.
+	fn parse_text(&mut self, command: &str) -> Parsed<String> {
+		self.skip_whitespace();
+		if self.peek() != Some('{') {
+			return Err(format!("{} must be followed by {{", command))
+		}
+		self.position += 1;
+		let mut text = String::new();
+		let mut depth = 0;
+		loop {
+			match self.peek() {
+				None => return Err(format!("{} is missing its closing }}", command)),
+				Some('}') if depth == 0 => break,
+				Some('}') => depth -= 1,
+				Some('{') => depth += 1,
+				Some('\\') => {
+					self.position += 1;
+					if let Some(c) = self.peek() {
+						text.push(c);
+						self.position += 1;
+					}
+					continue
+				},
+				_ => {},
+			}
+			if let Some(c) = self.peek() {
+				text.push(c);
+			}
+			self.position += 1;
+		}
+		self.position += 1;
+		Ok(text)
+	}
+
+	fn parse_delimiter(&mut self, command: &str) -> Parsed<String> {
+		self.skip_whitespace();
+		let delimiter = match self.peek() {
+			Some('.') => Some(String::new()),
+			Some('<') => Some("⟨".to_string()),
+			Some('>') => Some("⟩".to_string()),
+			Some(c) if "()[]|/".contains(c) => Some(c.to_string()),
+			Some('\\') => {
+				let name = self.peek_command().unwrap_or_default();
+				self.position += name.chars().count();
+				lookup(OPERATORS, &name).map(|c| c.to_string())
+			},
+			_ => None,
+		};
+		self.position += 1;
+		delimiter.ok_or_else(|| format!("{} must be followed by a delimiter", command))
+	}
+
+	fn parse_left(&mut self) -> Parsed<Atom> {
+		let mut items = vec![fence(&self.parse_delimiter("\\left")?)];
+		loop {
+			items.push(self.parse_row(false)?);
+			if self.eat_command("middle") {
+				items.push(fence(&self.parse_delimiter("\\middle")?));
+			} else if self.eat_command("right") {
+				items.push(fence(&self.parse_delimiter("\\right")?));
+				break
+			} else if self.peek().is_none() {
+				return Err("\\left without a matching \\right".to_string())
+			} else {
+				return Err(self.stray())
+			}
+		}
+		Ok(Atom::new(["<mrow>", &items.concat(), "</mrow>"].concat()))
+	}
+
+	fn parse_environment(&mut self) -> Parsed<Atom> {
+		let name = self.parse_text("\\begin")?;
+		let (open, close) = match name.as_str() {
+			"pmatrix" => ("(", ")"),
+			"bmatrix" => ("[", "]"),
+			"Bmatrix" | "cases" => ("{", ""),
+			"vmatrix" => ("|", "|"),
+			"Vmatrix" => ("‖", "‖"),
+			"matrix" | "smallmatrix" | "aligned" | "align" | "align*" | "split" | "gathered" | "gather" | "gather*" | "array" => ("", ""),
+			_ => return Err(format!("unknown environment {}", name)),
+		};
+		let close = if name == "Bmatrix" { "}" } else { close };
+		let spec = if name == "array" {
+			self.parse_text("\\begin{array}")?
+		} else {
+			String::new()
+		};
+
+		let mut rows = Vec::new();
+		let mut cells = Vec::new();
+		loop {
+			self.skip_whitespace();
+			if self.eat_command("hline") {
+				continue
+			}
+			cells.push(self.parse_row(false)?);
+			if self.peek() == Some('&') {
+				self.position += 1;
+			} else if self.eat_command("\\") {
+				rows.push(std::mem::take(&mut cells));
+			} else if self.eat_command("end") {
+				let end = self.parse_text("\\end")?;
+				if end != name {
+					return Err(format!("\\begin{{{}}} is ended by \\end{{{}}}", name, end))
+				}
+				rows.push(cells);
+				break
+			} else if self.peek().is_none() {
+				return Err(format!("\\begin{{{}}} is missing its \\end", name))
+			} else {
+				return Err(self.stray())
+			}
+		}
+		// A trailing \\ leaves an empty row behind.
+		if rows.len() > 1 && rows.last().is_some_and(|row| row.iter().all(String::is_empty)) {
+			rows.pop();
+		}
+
+		let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+		let align: Vec<&str> = match name.as_str() {
+			"cases" => vec!["left"; columns],
+			"aligned" | "align" | "align*" | "split" => (0..columns).map(|column| if column % 2 == 0 { "right" } else { "left" }).collect(),
+			"array" => spec.chars().filter_map(|c| match c {
+				'l' => Some("left"),
+				'c' => Some("center"),
+				'r' => Some("right"),
+				_ => None,
+			}).collect(),
+			_ => vec!["center"; columns],
+		};
+		let spacing = if align.contains(&"right") && name != "array" { " columnspacing=\"0em\"" } else { "" };
+		let mut table = format!("<mtable columnalign=\"{}\"{}>", align.join(" "), spacing);
+		for cells in rows {
+			table.push_str("<mtr>");
+			for cell in cells {
+				table.push_str(&["<mtd>", &cell, "</mtd>"].concat());
+			}
+			table.push_str("</mtr>");
+		}
+		table.push_str("</mtable>");
+
+		if open.is_empty() && close.is_empty() {
+			Ok(Atom::new(table))
+		} else {
+			Ok(Atom::new(["<mrow>", &fence(open), &table, &fence(close), "</mrow>"].concat()))
+		}
+	}
+
+	// \text and its variants, whose argument is text rather than math.
+	fn parse_text_command(&mut self, name: &str) -> Parsed<String> {
+		let text = self.parse_text(&["\\", name].concat())?;
+		let variant = match name {
+			"textbf" => " mathvariant=\"bold\"",
+			"textit" => " mathvariant=\"italic\"",
+			"texttt" => " mathvariant=\"monospace\"",
+			"textsf" => " mathvariant=\"sans-serif\"",
+			_ => "",
+		};
+		// Spaces at either end would otherwise be collapsed.
+		let text = escape(&text);
+		let trimmed = text.trim();
+		let start = if text.starts_with(char::is_whitespace) { "\u{a0}" } else { "" };
+		let end = if text.ends_with(char::is_whitespace) && !trimmed.is_empty() { "\u{a0}" } else { "" };
+		Ok(format!("<mtext{}>{}{}{}</mtext>", variant, start, trimmed, end))
+	}
+
+	fn parse_command(&mut self) -> Parsed<Atom> {
+		let name = self.peek_command().ok_or_else(|| "\\ at the end of the math".to_string())?;
+		self.position += 1 + name.chars().count();
+
+		let mathml = match name.as_str() {
+			"frac" | "dfrac" | "tfrac" | "cfrac" => {
+				let numerator = self.parse_argument(&["\\", &name].concat())?;
+				let denominator = self.parse_argument(&["\\", &name].concat())?;
+				["<mfrac>", &numerator, &denominator, "</mfrac>"].concat()
+			},
+			"binom" | "dbinom" | "tbinom" => {
+				let top = self.parse_argument(&["\\", &name].concat())?;
+				let bottom = self.parse_argument(&["\\", &name].concat())?;
+				["<mrow><mo>(</mo><mfrac linethickness=\"0\">", &top, &bottom, "</mfrac><mo>)</mo></mrow>"].concat()
+			},
+			"sqrt" => {
+				self.skip_whitespace();
+				if self.peek() == Some('[') {
+					self.position += 1;
+					let index = self.parse_row(true)?;
+					self.expect(']')?;
+					let radicand = self.parse_argument("\\sqrt")?;
+					["<mroot>", &radicand, &index, "</mroot>"].concat()
+				} else {
+					["<msqrt>", &self.parse_argument("\\sqrt")?, "</msqrt>"].concat()
+				}
+			},
+			"left" => return self.parse_left(),
+			"begin" => return self.parse_environment(),
+			"big" | "bigl" | "bigr" | "bigm" | "Big" | "Bigl" | "Bigr" | "Bigm" | "bigg" | "biggl" | "biggr" | "biggm" | "Bigg" | "Biggl" | "Biggr" | "Biggm" => {
+				let size = match name.trim_end_matches(['l', 'r', 'm']) {
+					"big" => "1.2em",
+					"Big" => "1.8em",
+					"bigg" => "2.4em",
+					_ => "3em",
+				};
+				let delimiter = self.parse_delimiter(&["\\", &name].concat())?;
+				format!("<mo stretchy=\"true\" minsize=\"{}\" maxsize=\"{}\">{}</mo>", size, size, escape(&delimiter))
+			},
+			"text" | "textrm" | "textnormal" | "mbox" | "textbf" | "textit" | "texttt" | "textsf" => self.parse_text_command(&name)?,
+			"operatorname" => {
+				let limits = self.peek() == Some('*');
+				if limits {
+					self.position += 1;
+				}
+				let text = self.parse_text("\\operatorname")?;
+				return Ok(Atom {
+					mathml: ["<mi>", &escape(&text), "</mi>"].concat(),
+					limits,
+				})
+			},
+			"not" => {
+				let negated = self.parse_argument("\\not")?;
+				if !negated.starts_with("<mo") {
+					return Err("\\not must be followed by a relation like = or \\in".to_string())
+				}
+				negated.replacen("</mo>", "\u{338}</mo>", 1)
+			},
+			"overset" | "stackrel" | "underset" => {
+				let script = self.parse_argument(&["\\", &name].concat())?;
+				let base = self.parse_argument(&["\\", &name].concat())?;
+				let element = if name == "underset" { "munder" } else { "mover" };
+				format!("<{}>{}{}</{}>", element, base, script, element)
+			},
+			"underline" => ["<munder accentunder=\"true\">", &self.parse_argument("\\underline")?, "<mo stretchy=\"true\">_</mo></munder>"].concat(),
+			"overbrace" | "underbrace" => {
+				let body = self.parse_argument(&["\\", &name].concat())?;
+				let mathml = if name == "overbrace" {
+					["<mover>", &body, "<mo stretchy=\"true\">⏞</mo></mover>"].concat()
+				} else {
+					["<munder>", &body, "<mo stretchy=\"true\">⏟</mo></munder>"].concat()
+				};
+				return Ok(Atom {
+					mathml,
+					limits: true,
+				})
+			},
+			"mod" | "bmod" => "<mo lspace=\"0.2222em\" rspace=\"0.2222em\">mod</mo>".to_string(),
+			"pmod" => ["<mrow><mo>(</mo><mo rspace=\"0.2222em\">mod</mo>", &self.parse_argument("\\pmod")?, "<mo>)</mo></mrow>"].concat(),
+			"displaystyle" | "textstyle" | "scriptstyle" | "scriptscriptstyle" => String::new(),
+			_ => {
+				if let Some(variant) = lookup(FONTS, &name) {
+					let outer = self.variant.replace(variant);
+					let argument = self.parse_argument(&["\\", &name].concat());
+					self.variant = outer;
+					argument?
+				} else if let Some((_, accent, stretchy)) = ACCENTS.iter().find(|(accent, _, _)| *accent == name) {
+					let base = self.parse_argument(&["\\", &name].concat())?;
+					let stretchy = if *stretchy { "true" } else { "false" };
+					format!("<mover accent=\"true\">{}<mo stretchy=\"{}\">{}</mo></mover>", base, stretchy, accent)
+				} else if let Some(atom) = symbol(&name) {
+					return Ok(atom)
+				} else {
+					return Err(format!("unknown command \\{}", name))
+				}
+			},
+		};
+		Ok(Atom::new(mathml))
+	}
+
+	// A single token or group, without any scripts. Arguments only take a single letter or digit, like in TeX.
+	fn parse_atom(&mut self, argument: bool) -> Parsed<Atom> {
+		if self.nesting == MAX_NESTING {
+			return Err(format!("the math is nested more than {} levels deep", MAX_NESTING))
+		}
+		self.nesting += 1;
+		let atom = self.parse_nested_atom(argument);
+		self.nesting -= 1;
+		atom
+	}
+
+	fn parse_nested_atom(&mut self, argument: bool) -> Parsed<Atom> {
+		let c = self.peek().ok_or_else(|| "the math ends too early".to_string())?;
+		let next_is_digit = self.chars.get(self.position + 1).is_some_and(char::is_ascii_digit);
+		let mathml = match c {
+			'{' => self.parse_group()?,
+			'\\' => return self.parse_command(),
+			'0'..='9' | '.' if c != '.' || next_is_digit => {
+				let mut number = String::new();
+				while let Some(c) = self.peek() {
+					let decimal = c == '.' && self.chars.get(self.position + 1).is_some_and(char::is_ascii_digit);
+					if !(c.is_ascii_digit() || decimal) || argument && !number.is_empty() {
+						break
+					}
+					number.push(self.variant.map_or(c, |variant| styled(c, variant)));
+					self.position += 1;
+				}
+				format!("<mn>{}</mn>", number)
+			},
+			c if c.is_alphabetic() => {
+				if let Some(variant) = self.variant {
+					// Letters in a font command are a single identifier, like "max" in \mathrm{max}.
+					let mut letters = String::new();
+					while let Some(c) = self.peek().filter(|c| c.is_alphabetic()) {
+						if argument && !letters.is_empty() {
+							break
+						}
+						letters.push(styled(c, variant));
+						self.position += 1;
+					}
+					if variant == Variant::Normal && letters.chars().count() == 1 {
+						format!("<mi mathvariant=\"normal\">{}</mi>", letters)
+					} else {
+						format!("<mi>{}</mi>", letters)
+					}
+				} else {
+					self.position += 1;
+					format!("<mi>{}</mi>", c)
+				}
+			},
+			'~' => {
+				self.position += 1;
+				"<mtext>\u{a0}</mtext>".to_string()
+			},
+			'\'' => {
+				self.position += 1;
+				"<mo>′</mo>".to_string()
+			},
+			'-' => {
+				self.position += 1;
+				"<mo>−</mo>".to_string()
+			},
+			'*' => {
+				self.position += 1;
+				"<mo>∗</mo>".to_string()
+			},
+			c => {
+				self.position += 1;
+				operator(c)
+			},
+		};
+		Ok(Atom::new(mathml))
+	}
+
+	// An atom along with any superscript, subscript or primes after it.
+	fn parse_scripted(&mut self) -> Parsed<String> {
+		let mut atom = if matches!(self.peek(), Some('^' | '_')) {
+			Atom::new("<mrow></mrow>".to_string())
+		} else {
+			self.parse_atom(false)?
+		};
+
+		let mut subscript = None;
+		let mut superscript = None;
+		let mut primes = String::new();
+		loop {
+			self.skip_whitespace();
+			match self.peek() {
+				Some('^') if superscript.is_some() => return Err("double superscript".to_string()),
+				Some('_') if subscript.is_some() => return Err("double subscript".to_string()),
+				Some('^') => {
+					self.position += 1;
+					superscript = Some(self.parse_argument("^")?);
+				},
+				Some('_') => {
+					self.position += 1;
+					subscript = Some(self.parse_argument("_")?);
+				},
+				Some('\'') if superscript.is_none() => {
+					self.position += 1;
+					primes.push('′');
+				},
+				_ => if self.eat_command("limits") {
+					atom.limits = true;
+				} else if self.eat_command("nolimits") {
+					atom.limits = false;
+				} else {
+					break
+				},
+			}
+		}
+		if !primes.is_empty() {
+			let primes = format!("<mo>{}</mo>", primes);
+			superscript = Some(superscript.map_or_else(|| primes.to_owned(), |superscript| row(&[primes.to_owned(), superscript])));
+		}
+
+		let (under, over, both) = if atom.limits && self.display {
+			("munder", "mover", "munderover")
+		} else {
+			("msub", "msup", "msubsup")
+		};
+		let base = atom.mathml;
+		Ok(match (subscript, superscript) {
+			(None, None) => base,
+			(Some(subscript), None) => format!("<{}>{}{}</{}>", under, base, subscript, under),
+			(None, Some(superscript)) => format!("<{}>{}{}</{}>", over, base, superscript, over),
+			(Some(subscript), Some(superscript)) => format!("<{}>{}{}{}</{}>", both, base, subscript, superscript, both),
+		})
+	}
+}
+
+/// Converts TeX math to a `MathML` `<math>` element.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the TeX is invalid, or uses something that isn't supported.
+pub fn tex_to_mathml(tex: &str, display: bool) -> Result<String, String> {
+	let mut parser = Parser {
+		chars: tex.chars().collect(),
+		position: 0,
+		display,
+		variant: None,
+		nesting: 0,
+	};
+	let body = parser.parse_row(false)?;
+	if parser.peek().is_some() {
+		return Err(parser.stray())
+	}
+	let display = if display { " display=\"block\"" } else { "" };
+	Ok(format!("<math{}><semantics><mrow>{}</mrow><annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>", display, body, escape(tex.trim())))
+}
+
+/// A formula found in Markdown, rendered to `MathML`.
+pub struct Formula {
+	pub display: bool,
+	pub mathml: String,
+}
+
+// Formulas are replaced with placeholders while the Markdown is rendered, so that TeX isn't mistaken for Markdown.
+fn placeholder(index: usize) -> String {
+	format!("\u{e000}{}\u{e001}", index)
+}
+
+// Finds the end of the math that starts at `start`, without crossing into the next paragraph.
+fn closing_dollar(bytes: &[u8], start: usize, display: bool) -> Option<usize> {
+	if !display && bytes.get(start).is_none_or(|byte| byte.is_ascii_whitespace() || *byte == b'$') {
+		return None
+	}
+	let mut position = start;
+	while position < bytes.len() {
+		match bytes[position] {
+			b'\\' => position += 1,
+			b'\n' if blank_line_after(bytes, position) => return None,
+			b'$' if display && bytes.get(position + 1) == Some(&b'$') => return Some(position),
+			// A dollar sign that can't close inline math isn't part of it, like the second one in "$5 and $10".
+			b'$' if !display => {
+				let closes = !bytes[position - 1].is_ascii_whitespace() && !bytes.get(position + 1).is_some_and(u8::is_ascii_digit);
+				return Some(position).filter(|_| closes)
+			},
+			_ => {},
+		}
+		position += 1;
+	}
+	None
+}
+
+/// Replaces the math in Markdown with placeholders, which [`restore`] replaces with the rendered formulas.
+///
+/// # Errors
+///
+/// Returns a message for every formula that can't be rendered, along with the line it's on.
+pub fn extract(input: &str) -> Result<(String, Vec<Formula>), Vec<String>> {
+	let (frontmatter, body) = frontmatter::split(input);
+	let lines_before = frontmatter.matches('\n').count();
+	let bytes = body.as_bytes();
+
+	let mut output = frontmatter.to_string();
+	let mut formulas = Vec::new();
+	let mut errors = Vec::new();
+	let mut copied = 0;
+	let mut position = 0;
+	let mut line_start = true;
+	while position < bytes.len() {
+		if line_start {
+			line_start = false;
+			if let Some(end) = fenced_code_end(body, position) {
+				position = end;
+				line_start = true;
+				continue
+			}
+		}
+		match bytes[position] {
+			b'\n' => {
+				line_start = true;
+				position += 1;
+			},
+			b'\\' if bytes.get(position + 1) != Some(&b'\n') => position += 2,
+			b'`' => {
+				let length = bytes[position..].iter().take_while(|byte| **byte == b'`').count();
+				position = closing_backticks(bytes, position + length, length).map_or(position, |end| end) + length;
+			},
+			b'$' => {
+				let display = bytes.get(position + 1) == Some(&b'$');
+				let delimiter = if display { 2 } else { 1 };
+				if let Some(end) = closing_dollar(bytes, position + delimiter, display) {
+					output.push_str(&body[copied..position]);
+					match tex_to_mathml(&body[position + delimiter..end], display) {
+						Ok(mathml) => {
+							output.push_str(&placeholder(formulas.len()));
+							formulas.push(Formula {
+								display,
+								mathml,
+							});
+						},
+						Err(message) => {
+							let line = lines_before + body[..position].matches('\n').count() + 1;
+							errors.push(format!("line {}: {}", line, message));
+							output.push_str(&body[position..end + delimiter]);
+						},
+					}
+					position = end + delimiter;
+					copied = position;
+				} else {
+					position += delimiter;
+				}
+			},
+			_ => position += 1,
+		}
+	}
+	output.push_str(&body[copied..]);
+
+	if errors.is_empty() {
+		Ok((output, formulas))
+	} else {
+		Err(errors)
+	}
+}
+
+/// Replaces the placeholders left by [`extract`] in rendered HTML with their formulas. Paragraphs holding only display math are replaced by the formula.
+#[must_use]
+pub fn restore(html: &str, formulas: &[Formula]) -> String {
+	let mut output = String::with_capacity(html.len());
+	let mut rest = html;
+	while let Some(start) = rest.find('\u{e000}') {
+		let end = if let Some(end) = rest[start..].find('\u{e001}') {
+			start + end
+		} else {
+			break
+		};
+		let formula = rest[start + '\u{e000}'.len_utf8()..end].parse().ok().and_then(|index: usize| formulas.get(index));
+		output.push_str(&rest[..start]);
+		rest = &rest[end + '\u{e001}'.len_utf8()..];
+		if let Some(formula) = formula {
+			if formula.display && output.ends_with("<p>") && rest.starts_with("</p>") {
+				output.truncate(output.len() - "<p>".len());
+				rest = &rest["</p>".len()..];
+			}
+			output.push_str(&formula.mathml);
+		}
+	}
+	output.push_str(rest);
+	output
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{config::{Backend, Markdown}, markdown::markdown_to_html};
+
+	fn formulas(input: &str) -> usize {
+		extract(input).unwrap().1.len()
+	}
+
+	#[test]
+	fn dollars_around_a_formula_are_math() {
+		let (text, formulas) = extract("a $x$ b").unwrap();
+		assert_eq!(text, ["a ", &placeholder(0), " b"].concat());
+		assert_eq!(formulas.len(), 1);
+		assert!(!formulas[0].display);
+
+		let (text, formulas) = extract("$$\nx\n$$").unwrap();
+		assert_eq!(text, placeholder(0));
+		assert!(formulas[0].display);
+	}
+
+	#[test]
+	fn dollars_that_are_not_delimiters_are_left_alone() {
+		for input in &["$5 and $10", "$ x$", "$x $", "$x$1", "$x\n\ny$"] {
+			assert_eq!(extract(input).unwrap().0, *input);
+			assert_eq!(formulas(input), 0, "{:?}", input);
+		}
+	}
+
+	#[test]
+	fn escaped_dollars_are_not_delimiters() {
+		assert_eq!(formulas("\\$x$"), 0);
+		assert_eq!(extract("\\$x$").unwrap().0, "\\$x$");
+	}
+
+	#[test]
+	fn code_and_frontmatter_are_skipped() {
+		assert_eq!(formulas("`$x$`"), 0);
+		assert_eq!(formulas("```\n$x$\n```\n"), 0);
+		let (text, formulas) = extract("+++\nt = \"$x$\"\n+++\n$y$").unwrap();
+		assert_eq!(text, ["+++\nt = \"$x$\"\n+++\n", &placeholder(0)].concat());
+		assert_eq!(formulas.len(), 1);
+	}
+
+	#[test]
+	fn inline_and_display_math_become_mathml() {
+		assert_eq!(
+			tex_to_mathml("x^2", false).unwrap(),
+			"<math><semantics><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow><annotation encoding=\"application/x-tex\">x^2</annotation></semantics></math>",
+		);
+		assert_eq!(
+			tex_to_mathml("\\frac{a}{b}", true).unwrap(),
+			"<math display=\"block\"><semantics><mrow><mfrac><mi>a</mi><mi>b</mi></mfrac></mrow><annotation encoding=\"application/x-tex\">\\frac{a}{b}</annotation></semantics></math>",
+		);
+		assert!(tex_to_mathml("\\alpha+1", false).unwrap().contains("<mi>α</mi><mo>+</mo><mn>1</mn>"));
+		assert!(tex_to_mathml("\\sum_{i=1}^n i", true).unwrap().contains("<munderover><mo>∑</mo>"));
+		let escaped = tex_to_mathml("a < b", false).unwrap();
+		assert!(escaped.contains("<mo>&lt;</mo>") && escaped.contains(">a &lt; b</annotation>"));
+	}
+
+	#[test]
+	fn invalid_tex_is_reported() {
+		assert_eq!(tex_to_mathml("\\foo", false), Err("unknown command \\foo".to_string()));
+		assert_eq!(tex_to_mathml("{x", false), Err("missing }".to_string()));
+		assert_eq!(tex_to_mathml("x}", false), Err("unexpected }".to_string()));
+		assert_eq!(tex_to_mathml("\\frac{a}", false), Err("\\frac is missing an argument".to_string()));
+		assert_eq!(tex_to_mathml("\\left( x", false), Err("\\left without a matching \\right".to_string()));
+		assert_eq!(tex_to_mathml("\\begin{matrix} a \\end{cases}", false), Err("\\begin{matrix} is ended by \\end{cases}".to_string()));
+	}
+
+	#[test]
+	fn deeply_nested_math_is_an_error() {
+		let nested = |open: &str, close: &str, depth: usize| [open.repeat(depth), "x".to_string(), close.repeat(depth)].concat();
+		assert!(tex_to_mathml(&nested("{", "}", MAX_NESTING - 1), false).is_ok());
+		let error = Err(format!("the math is nested more than {} levels deep", MAX_NESTING));
+		for (open, close) in &[("{", "}"), ("\\left(", "\\right)"), ("\\begin{matrix}", "\\end{matrix}"), ("\\frac{", "}{y}"), ("\\sqrt{", "}")] {
+			// Commands with a group argument nest twice per level.
+			assert!(tex_to_mathml(&nested(open, close, MAX_NESTING / 3), true).is_ok(), "{}", open);
+			assert_eq!(tex_to_mathml(&nested(open, close, 100_000), false), error, "{}", open);
+		}
+	}
+
+	#[test]
+	fn diagnostics_start_with_the_line() {
+		assert_eq!(
+			extract("a\n$\\foo$\nb $\\frac$").err(),
+			Some(vec!["line 2: unknown command \\foo".to_string(), "line 3: \\frac is missing an argument".to_string()]),
+		);
+	}
+
+	#[test]
+	fn display_math_is_taken_out_of_its_paragraph() {
+		let formulas = [
+			Formula { display: false, mathml: "<math>x</math>".to_string() },
+			Formula { display: true, mathml: "<math display=\"block\">y</math>".to_string() },
+		];
+		let html = ["<p>a ", &placeholder(0), "</p>\n<p>", &placeholder(1), "</p>\n"].concat();
+		assert_eq!(restore(&html, &formulas), "<p>a <math>x</math></p>\n<math display=\"block\">y</math>\n");
+	}
+
+	#[test]
+	fn both_backends_render_math() {
+		for backend in &[Backend::Comrak, Backend::PulldownCmark] {
+			let options = Markdown { math: true, backend: Some(*backend), ..Markdown::default() };
+			let html = markdown_to_html("Costs \\$5, $x^2$ and\n\n$$\n\\frac{a}{b}\n$$\n\n`$y$`\n", &options).unwrap().html;
+			assert!(html.starts_with("<p>Costs $5, <math><semantics>"), "{}", html);
+			assert!(html.contains("</p>\n<math display=\"block\"><semantics><mrow><mfrac>"), "{}", html);
+			assert!(html.ends_with("<p><code>$y$</code></p>\n"), "{}", html);
+
+			let err = markdown_to_html("ok\n\n$\\foo$\n", &options).err().unwrap().to_string();
+			assert!(err.ends_with("line 3: unknown command \\foo"), "{}", err);
+		}
+	}
+}
@@ -0,0 +1,401 @@
+use crate::{config::{Config, FailurePolicy, Plugins}, error::{Error, Result}, protocol::{self, Metadata, PageInfo}, site::{relative_path, Verbosity}};
+use glob::Pattern;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+use std::{collections::HashMap, fs, mem, thread, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard, PoisonError}};
+
+/// Directory that plugin executables are loaded from.
+pub const PLUGIN_DIR: &str = "plugins/";
+
+/// Environment variable pointing plugins at the config file used for the current build.
+pub const CONFIG_ENV: &str = "KATSITE_CONFIG";
+
+/// Environment variable telling plugins how much progress output to print (`quiet`, `normal` or `verbose`).
+pub const VERBOSITY_ENV: &str = "KATSITE_VERBOSITY";
+
+/// Hooks that plugins can be run for. `preinit`, `asyncinit` and `postinit` are run once per build, and `markdown` and `html` once per input file.
+pub const HOOKS: &[&str] = &["preinit", "asyncinit", "markdown", "html", "postinit"];
+
+// Hooks that plugins without a `hooks` list are run for. Newer hooks have to be opted into.
+const DEFAULT_HOOKS: &[&str] = &["asyncinit", "markdown", "postinit"];
+
+/// Settings a plugin declares in `plugins/<name>.toml`, next to its executable. Plugins without a manifest use the defaults.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Manifest {
+	/// Which plugin protocol to use. Version 1 pipes raw file contents, and version 2 exchanges JSON messages (see [`protocol`]).
+	pub protocol: u32,
+	/// Start the plugin once per build and send it every request, instead of starting it once per hook and file. Requires protocol version 2.
+	pub persistent: bool,
+	/// How many copies of a persistent plugin may run at once. Defaults to the number of CPUs.
+	pub workers: Option<usize>,
+	/// Hooks the plugin is run for. Defaults to `asyncinit`, `markdown` and `postinit`.
+	pub hooks: Option<Vec<String>>,
+	/// Patterns matching the input files that per-file hooks are run for, relative to the site's directory. Defaults to every input file.
+	pub files: Option<Vec<String>>,
+	/// Plugins that this plugin must run before. Plugins that aren't loaded are ignored.
+	pub before: Vec<String>,
+	/// Plugins that this plugin must run after. Plugins that aren't loaded are ignored.
+	pub after: Vec<String>,
+	/// Whether the plugin may run alongside other plugins, and more than once at a time. Plugins that can't are run on their own for site-wide hooks, and for one file at a time.
+	pub parallel_safe: bool,
+	#[serde(skip)]
+	patterns: Vec<Pattern>,
+}
+
+impl Default for Manifest {
+	fn default() -> Self {
+		Self {
+			protocol: 1,
+			persistent: false,
+			workers: None,
+			hooks: None,
+			files: None,
+			before: Vec::new(),
+			after: Vec::new(),
+			parallel_safe: true,
+			patterns: Vec::new(),
+		}
+	}
+}
+
+impl Manifest {
+	/// Whether the plugin is run for a hook.
+	#[must_use]
+	pub fn handles(&self, hook: &str) -> bool {
+		self.hooks.as_ref().map_or_else(|| DEFAULT_HOOKS.contains(&hook), |hooks| hooks.iter().any(|handled| handled == hook))
+	}
+
+	/// Whether per-file hooks are run for an input file.
+	#[must_use]
+	pub fn applies_to(&self, source: &Path) -> bool {
+		self.files.is_none() || {
+			let source = relative_path(source);
+			self.patterns.iter().any(|pattern| pattern.matches_path(&source))
+		}
+	}
+}
+
+/// The path to a plugin's manifest.
+#[must_use]
+pub fn manifest_path(plugin: &str) -> PathBuf {
+	Path::new(PLUGIN_DIR).join([plugin, ".toml"].concat())
+}
+
+/// The path to a plugin's WebAssembly module, which is used instead of a program with the plugin's name if it exists.
+#[must_use]
+pub fn wasm_path(plugin: &str) -> PathBuf {
+	Path::new(PLUGIN_DIR).join([plugin, ".wasm"].concat())
+}
+
+/// Loads a plugin's manifest, if it has one.
+///
+/// # Errors
+///
+/// Returns [`Error::Manifest`] if the manifest can't be parsed, or declares an unsupported protocol.
+pub fn load_manifest(plugin: &str) -> Result<Manifest> {
+	let path = manifest_path(plugin);
+	let mut manifest: Manifest = if let Ok(input) = fs::read_to_string(&path) {
+		toml::from_str(&input).map_err(|err| Error::Manifest {
+			plugin: plugin.to_string(),
+			message: err.to_string(),
+		})?
+	} else {
+		Manifest::default()
+	};
+
+	if manifest.protocol == 0 || manifest.protocol > protocol::VERSION {
+		return Err(Error::Manifest {
+			plugin: plugin.to_string(),
+			message: format!("protocol version {} is not supported", manifest.protocol),
+		})
+	}
+	if manifest.persistent && manifest.protocol < 2 {
+		return Err(Error::Manifest {
+			plugin: plugin.to_string(),
+			message: "persistent plugins must use protocol version 2".to_string(),
+		})
+	}
+	if let Some(hook) = manifest.hooks.iter().flatten().find(|hook| !HOOKS.contains(&hook.as_str())) {
+		return Err(Error::Manifest {
+			plugin: plugin.to_string(),
+			message: format!("unknown hook {:#?}", hook),
+		})
+	}
+	manifest.patterns = manifest.files.iter().flatten().map(|pattern| {
+		Pattern::new(pattern.trim_start_matches("./")).map_err(|err| Error::Manifest {
+			plugin: plugin.to_string(),
+			message: format!("invalid file pattern {:#?}: {}", pattern, err),
+		})
+	}).collect::<Result<_>>()?;
+	Ok(manifest)
+}
+
+/// A file being run through a per-file hook.
+pub struct Page<'a> {
+	/// The input file.
+	pub source: &'a Path,
+	/// Where the file's HTML is written.
+	pub output: &'a Path,
+	/// The file's Markdown source for the `markdown` hook, or its rendered HTML for the `html` hook.
+	pub content: Vec<u8>,
+	/// The file's frontmatter, if it's already known. Otherwise, it can be parsed from `content`.
+	pub frontmatter: Option<&'a toml::Value>,
+	/// Metadata that plugins attached to the file.
+	pub metadata: Metadata,
+}
+
+/// A plugin, which is run for every hook its manifest declares. Hooks that a plugin doesn't implement do nothing.
+///
+/// Built-in plugins implement this to run in-process (see [`Registry`](crate::registry::Registry)), and [`ExternalPlugin`](crate::external::ExternalPlugin) implements it for programs in the `plugins/` directory.
+pub trait Plugin: Send + Sync {
+	/// The name used in `plugins_list`.
+	fn name(&self) -> &str;
+
+	fn manifest(&self) -> &Manifest;
+
+	/// Whether the plugin reads [`PluginEnv::pages`], which is only collected if a plugin does.
+	fn wants_pages(&self) -> bool {
+		false
+	}
+
+	/// Runs before input files are found, so it can generate them.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the plugin fails, which stops the build.
+	fn preinit(&self, _env: &PluginEnv) -> Result<()> {
+		Ok(())
+	}
+
+	/// Runs while input files are converted, usually to build assets.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the plugin fails.
+	fn asyncinit(&self, _env: &PluginEnv) -> Result<()> {
+		Ok(())
+	}
+
+	/// Runs after every input file is converted, usually to apply templates.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the plugin fails.
+	fn postinit(&self, _env: &PluginEnv) -> Result<()> {
+		Ok(())
+	}
+
+	/// Runs for each input file before it's converted to HTML.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the plugin fails, which fails the file.
+	fn markdown(&self, _page: &mut Page, _env: &PluginEnv) -> Result<()> {
+		Ok(())
+	}
+
+	/// Runs for each input file after it's converted to HTML, before it's written.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the plugin fails, which fails the file.
+	fn html(&self, _page: &mut Page, _env: &PluginEnv) -> Result<()> {
+		Ok(())
+	}
+}
+
+// Whether a plugin has to wait for another one to finish.
+fn depends_on(plugin: &dyn Plugin, other: &dyn Plugin) -> bool {
+	plugin.manifest().after.iter().any(|after| after == other.name()) || other.manifest().before.iter().any(|before| before == plugin.name())
+}
+
+/// Orders plugins so that each one runs after the plugins it depends on, keeping the order of `plugins_list` where their manifests don't say otherwise.
+///
+/// # Errors
+///
+/// Returns [`Error::Manifest`] if plugins depend on each other in a cycle.
+pub(crate) fn resolve_order(mut remaining: Vec<Arc<dyn Plugin>>) -> Result<Vec<Arc<dyn Plugin>>> {
+	let mut order = Vec::with_capacity(remaining.len());
+	while !remaining.is_empty() {
+		let ready = remaining.iter().position(|plugin| {
+			!remaining.iter().any(|other| other.name() != plugin.name() && depends_on(plugin.as_ref(), other.as_ref()))
+		});
+		if let Some(ready) = ready {
+			order.push(remaining.remove(ready));
+		} else {
+			return Err(Error::Manifest {
+				plugin: remaining[0].name().to_string(),
+				message: format!("plugins {} depend on each other in a cycle", remaining.iter().map(|plugin| plugin.name()).collect::<Vec<_>>().join(", ")),
+			})
+		}
+	}
+	Ok(order)
+}
+
+// Splits plugins into groups that can run in parallel, one after another. Plugins that depend on an earlier plugin in the group, or aren't parallel-safe, start a new group.
+fn batches<'a>(plugins: &[&'a Arc<dyn Plugin>]) -> Vec<Vec<&'a Arc<dyn Plugin>>> {
+	let mut batches: Vec<Vec<&Arc<dyn Plugin>>> = Vec::new();
+	for &plugin in plugins {
+		let joins = plugin.manifest().parallel_safe && batches.last().is_some_and(|batch| {
+			batch.iter().all(|other| other.manifest().parallel_safe && !depends_on(plugin.as_ref(), other.as_ref()))
+		});
+		if let (true, Some(batch)) = (joins, batches.last_mut()) {
+			batch.push(plugin);
+		} else {
+			batches.push(vec![plugin]);
+		}
+	}
+	batches
+}
+
+/// Settings shared by every plugin during a build.
+#[derive(Clone)]
+pub struct PluginEnv {
+	/// The config file that plugins should read, which includes any overrides.
+	pub config_path: PathBuf,
+	/// The whole config, which plugins using protocol version 2 are sent their section of.
+	pub config: Arc<toml::Value>,
+	/// Timeouts and failure policies.
+	pub settings: Plugins,
+	pub verbosity: Verbosity,
+	/// Plugins to run, in the order resolved from their manifests.
+	pub plugins: Vec<Arc<dyn Plugin>>,
+	/// Every page in the site, which is only collected if a plugin wants it.
+	pub pages: Arc<Vec<PageInfo>>,
+	// Held while a plugin that isn't parallel-safe is running.
+	locks: Arc<HashMap<String, Mutex<()>>>,
+}
+
+impl PluginEnv {
+	#[must_use]
+	pub fn new(config_path: PathBuf, config: &Config, verbosity: Verbosity, plugins: Vec<Arc<dyn Plugin>>) -> Self {
+		let locks = plugins.iter().filter(|plugin| !plugin.manifest().parallel_safe).map(|plugin| {
+			(plugin.name().to_string(), Mutex::new(()))
+		}).collect();
+		Self {
+			config_path,
+			config: Arc::new(config.raw.to_owned()),
+			settings: config.plugins.to_owned(),
+			verbosity,
+			plugins,
+			pages: Arc::new(Vec::new()),
+			locks: Arc::new(locks),
+		}
+	}
+
+	// Keeps plugins that aren't parallel-safe from running more than once at a time.
+	fn lock(&self, plugin: &str) -> Option<MutexGuard<'_, ()>> {
+		self.locks.get(plugin).map(|lock| lock.lock().unwrap_or_else(PoisonError::into_inner))
+	}
+
+	/// Whether any plugin wants the list of pages.
+	#[must_use]
+	pub fn wants_pages(&self) -> bool {
+		self.plugins.iter().any(|plugin| plugin.wants_pages())
+	}
+
+	/// What to do about an error, depending on which plugin caused it. Errors that weren't caused by a plugin misbehaving always fail.
+	#[must_use]
+	pub fn on_failure(&self, err: &Error) -> FailurePolicy {
+		err.plugin_name().map_or(FailurePolicy::Fail, |plugin| self.settings.on_failure(plugin))
+	}
+
+	/// A plugin's section of the config file, which is named after it, like `[katsite_essentials]`.
+	#[must_use]
+	pub fn config_section(&self, plugin: &str) -> Option<&toml::Value> {
+		self.config.get(plugin.replace('-', "_"))
+	}
+}
+
+/// Runs a single plugin for a site-wide hook (`preinit`, `asyncinit` or `postinit`), waiting for it to finish.
+///
+/// # Errors
+///
+/// Returns whatever error the plugin fails with. Plugin programs fail with [`Error::Plugin`] if they can't be started, [`Error::PluginFailed`] if they exit unsuccessfully or time out, or [`Error::Diagnostics`] if they report errors.
+pub fn init_plugin(hook: &str, plugin: &dyn Plugin, env: &PluginEnv) -> Result<()> {
+	let _lock = env.lock(plugin.name());
+	match hook {
+		"preinit" => plugin.preinit(env),
+		"asyncinit" => plugin.asyncinit(env),
+		"postinit" => plugin.postinit(env),
+		_ => Ok(()),
+	}
+}
+
+/// Runs a site-wide hook for every plugin that handles it on a background thread, either one after another or in parallel. Plugins always start after the plugins they depend on have finished.
+///
+/// Plugins that fail with the `skip` failure policy only print a warning.
+#[must_use]
+pub fn init_plugins(hook: String, ordered: bool, env: PluginEnv) -> thread::JoinHandle<Result<()>> {
+	thread::spawn(move || {
+		let plugins: Vec<_> = env.plugins.iter().filter(|plugin| plugin.manifest().handles(&hook)).collect();
+		let run = |plugin: &&Arc<dyn Plugin>| {
+			init_plugin(&hook, plugin.as_ref(), &env).or_else(|err| {
+				if env.on_failure(&err) == FailurePolicy::Skip {
+					eprintln!("Warning: {}", err);
+					Ok(())
+				} else {
+					Err(err)
+				}
+			})
+		};
+		if ordered {
+			plugins.iter().try_for_each(run)
+		} else {
+			batches(&plugins).iter().try_for_each(|batch| {
+				batch.par_iter().try_for_each(run)
+			})
+		}
+	})
+}
+
+/// Pipes `buffer` through every plugin that applies to the file for a per-file hook (`markdown` or `html`), replacing it with the final plugin's output.
+///
+/// Plugins are given `frontmatter` if it's known, and the metadata that plugins attached to the file is returned.
+///
+/// # Errors
+///
+/// Returns the first error that a plugin fails with. See [`init_plugin`] for the errors that plugin programs fail with. Plugins using protocol version 2 can also fail with [`Error::Protocol`], and the file's frontmatter must be valid.
+pub fn run_plugins(buffer: &mut Vec<u8>, hook: &str, source: &Path, output: &Path, frontmatter: Option<&toml::Value>, env: &PluginEnv) -> Result<Metadata> {
+	let mut page = Page {
+		source,
+		output,
+		content: mem::take(buffer),
+		frontmatter,
+		metadata: Metadata::new(),
+	};
+	let result = env.plugins.iter().filter(|plugin| plugin.manifest().handles(hook) && plugin.manifest().applies_to(source)).try_for_each(|plugin| {
+		let _lock = env.lock(plugin.name());
+		match hook {
+			"markdown" => plugin.markdown(&mut page, env),
+			"html" => plugin.html(&mut page, env),
+			_ => Ok(()),
+		}
+	});
+	*buffer = page.content;
+	let metadata = page.metadata;
+	result.map(|()| metadata)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reported_errors_follow_the_plugins_failure_policy() {
+		let config = Config::from_toml("plugins_list = []\n[files]\ninput_glob = \"./*.md\"\noutput_dir = \".\"\n[plugins.katsite-essentials]\non_failure = \"skip\"\n").unwrap();
+		let env = PluginEnv::new(PathBuf::from("conf.toml"), &config, Verbosity::Quiet, Vec::new());
+		let diagnostics = |plugin: &str| Error::Diagnostics {
+			plugin: plugin.to_string(),
+			messages: vec!["index.md: Unable to render template!".to_string()],
+		};
+		assert_eq!(env.on_failure(&diagnostics("katsite-essentials")), FailurePolicy::Skip);
+		assert_eq!(env.on_failure(&Error::File {
+			path: PathBuf::from("index.md"),
+			source: Box::new(diagnostics("katsite-essentials")),
+		}), FailurePolicy::Skip);
+		assert_eq!(env.on_failure(&diagnostics("other")), FailurePolicy::Fail);
+		assert_eq!(env.on_failure(&Error::Math(Vec::new())), FailurePolicy::Fail);
+	}
+}
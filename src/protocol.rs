@@ -0,0 +1,149 @@
+//! Version 2 of the plugin protocol, which plugins opt into with `protocol = 2` in their manifest (`plugins/<name>.toml`).
+//!
+//! Plugins are still started once per hook (and once per file for the `markdown` and `html` hooks), with the same arguments as before. Instead of raw file contents, they're sent a JSON [`Request`] on stdin, and reply with a JSON [`Response`] on stdout. As stdout is used for the response, progress output should go to stderr.
+//!
+//! Plugins that set `persistent = true` are instead started once per build with the `persistent` argument, and handle every request over the same stdin/stdout. Each message is framed as its length in bytes, a newline, and then the message itself (see [`read_frame`] and [`write_frame`]), and can be up to 256 MiB long. Several copies of a plugin may be started to handle files in parallel, up to `workers`. The plugin should exit once stdin is closed. This saves starting the plugin for every file, which makes building pages through a plugin over ten times faster (see `benches/plugins.rs`).
+//!
+//! ```json
+//! {"content": "# Hello", "metadata": {"words": 1}, "diagnostics": [{"level": "warning", "message": "Page is very short", "line": 1}]}
+//! ```
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use std::{convert::TryFrom, io::{self, BufRead, Read, Write}, path::{Path, PathBuf}};
+
+/// The JSON-based plugin protocol version.
+pub const VERSION: u32 = 2;
+
+/// The longest message that [`read_frame`] accepts, in bytes.
+pub const MAX_FRAME: u64 = 256 * 1024 * 1024;
+
+// The longest length prefix, which is the most digits a `u64` has and a newline.
+const MAX_HEADER: u64 = 21;
+
+/// Metadata that plugins attach to a page, which is passed back to plugins in later hooks.
+pub type Metadata = Map<String, Value>;
+
+/// A page as seen by plugins.
+#[derive(Serialize, Clone, Debug)]
+pub struct PageInfo {
+	/// The input file.
+	pub source: PathBuf,
+	/// Where the page's HTML is written.
+	pub output: PathBuf,
+	pub frontmatter: toml::Value,
+	pub metadata: Metadata,
+}
+
+/// The message sent to a plugin on stdin.
+#[derive(Serialize)]
+pub struct Request<'a> {
+	pub protocol: u32,
+	pub hook: &'a str,
+	/// The input file, for per-file hooks.
+	pub source: Option<&'a Path>,
+	/// Where the file's HTML is written, for per-file hooks.
+	pub output: Option<&'a Path>,
+	/// The file's contents, for per-file hooks. This is the Markdown source for the `markdown` hook, and the rendered HTML for the `html` hook.
+	pub content: Option<&'a str>,
+	pub frontmatter: Option<&'a toml::Value>,
+	/// The plugin's section of the config file.
+	pub config: Option<&'a toml::Value>,
+	/// Every page in the site.
+	pub pages: &'a [PageInfo],
+}
+
+/// The message a plugin replies with on stdout. Every field is optional.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Response {
+	/// The file's new contents, for per-file hooks. The contents are left unchanged if this is missing.
+	pub content: Option<String>,
+	/// Metadata to attach to the file.
+	pub metadata: Metadata,
+	pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A problem reported by a plugin.
+#[derive(Deserialize, Debug)]
+pub struct Diagnostic {
+	pub level: Level,
+	pub message: String,
+	/// The line in the file that the problem is on, if it's about a specific line.
+	pub line: Option<usize>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+	/// Fails the file, or the build for site-wide hooks.
+	Error,
+	Warning,
+	Info,
+}
+
+/// Reads a length-prefixed message, returning `None` once the stream is closed.
+///
+/// # Errors
+///
+/// Returns an error if reading fails, the length prefix is invalid, or the message is longer than [`MAX_FRAME`].
+pub fn read_frame<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+	let mut header = String::new();
+	if reader.by_ref().take(MAX_HEADER).read_line(&mut header)? == 0 {
+		return Ok(None)
+	}
+	let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+	let length: u64 = header.trim().parse().map_err(|_| invalid(format!("invalid frame length {:#?}", header.trim())))?;
+	if length > MAX_FRAME {
+		return Err(invalid(format!("frame length {} is over the limit of {} bytes", length, MAX_FRAME)))
+	}
+	// The message is read as it arrives, rather than trusting the length enough to allocate all of it upfront.
+	let mut message = Vec::new();
+	reader.take(length).read_to_end(&mut message)?;
+	if u64::try_from(message.len()).unwrap_or(u64::MAX) < length {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the stream closed partway through a frame"))
+	}
+	Ok(Some(message))
+}
+
+/// Writes a length-prefixed message.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_frame<W: Write>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+	writer.write_all(message.len().to_string().as_bytes())?;
+	writer.write_all(b"\n")?;
+	writer.write_all(message)?;
+	writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frames_round_trip() {
+		let mut stream = Vec::new();
+		write_frame(&mut stream, b"{\"content\": \"a\nb\"}").unwrap();
+		write_frame(&mut stream, b"").unwrap();
+		let mut reader = stream.as_slice();
+		assert_eq!(read_frame(&mut reader).unwrap().as_deref(), Some(&b"{\"content\": \"a\nb\"}"[..]));
+		assert_eq!(read_frame(&mut reader).unwrap().as_deref(), Some(&b""[..]));
+		assert_eq!(read_frame(&mut reader).unwrap(), None);
+	}
+
+	#[test]
+	fn oversized_frames_are_rejected() {
+		let header = format!("{}\n", MAX_FRAME + 1);
+		assert_eq!(read_frame(&mut header.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(read_frame(&mut &b"18446744073709551615\n"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(read_frame(&mut &b"99999999999999999999999999\n"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(read_frame(&mut &b"ten\n"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn truncated_frames_are_errors() {
+		assert_eq!(read_frame(&mut &b"10\nshort"[..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+	}
+}
@@ -0,0 +1,73 @@
+use crate::{config::Config, error::Result, external::ExternalPlugin, plugins::{load_manifest, wasm_path, Manifest, Plugin}};
+use std::{collections::HashMap, sync::Arc};
+
+/// Creates a built-in plugin from the site's config and the plugin's manifest.
+pub type Constructor = fn(&Config, Manifest) -> Result<Arc<dyn Plugin>>;
+
+/// The plugins that can be listed in `plugins_list`. Built-in plugins run in-process, and any other plugin is loaded from the `plugins/` directory, either as a program or as a sandboxed WebAssembly module.
+#[derive(Clone)]
+pub struct Registry {
+	builtins: HashMap<String, Constructor>,
+}
+
+impl Default for Registry {
+	/// Every built-in plugin enabled by cargo features.
+	fn default() -> Self {
+		#[allow(unused_mut)]
+		let mut registry = Self::empty();
+		#[cfg(feature = "essentials")]
+		registry.register("katsite-essentials", crate::essentials::load);
+		registry
+	}
+}
+
+impl Registry {
+	/// A registry without any built-in plugins, which loads every plugin from the `plugins/` directory.
+	#[must_use]
+	pub fn empty() -> Self {
+		Self {
+			builtins: HashMap::new(),
+		}
+	}
+
+	/// Adds a built-in plugin, replacing any other built-in plugin with the same name.
+	pub fn register(&mut self, name: &str, constructor: Constructor) {
+		self.builtins.insert(name.to_string(), constructor);
+	}
+
+	/// Whether a plugin is built in.
+	#[must_use]
+	pub fn is_builtin(&self, name: &str) -> bool {
+		self.builtins.contains_key(name)
+	}
+
+	/// Loads a plugin along with its manifest. Built-in plugins are preferred over anything with the same name in the `plugins/` directory, and WebAssembly modules (`plugins/<name>.wasm`) are preferred over programs.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Manifest`](crate::Error::Manifest) if the plugin's manifest is invalid, [`Error::Wasm`](crate::Error::Wasm) if its WebAssembly module can't be loaded, or whatever error a built-in plugin fails to load with.
+	pub fn load(&self, name: &str, config: &Config) -> Result<Arc<dyn Plugin>> {
+		let manifest = load_manifest(name)?;
+		if let Some(constructor) = self.builtins.get(name) {
+			constructor(config, manifest)
+		} else if wasm_path(name).is_file() {
+			load_wasm(name, config, manifest)
+		} else {
+			Ok(Arc::new(ExternalPlugin::new(name, manifest)))
+		}
+	}
+}
+
+#[cfg(feature = "wasm")]
+fn load_wasm(name: &str, config: &Config, manifest: Manifest) -> Result<Arc<dyn Plugin>> {
+	let module = crate::wasm::WasmModule::load(name, config, &manifest)?;
+	Ok(Arc::new(ExternalPlugin::wasm(name, manifest, module)))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn load_wasm(name: &str, _config: &Config, _manifest: Manifest) -> Result<Arc<dyn Plugin>> {
+	Err(crate::Error::Wasm {
+		plugin: name.to_string(),
+		message: "katsite was built without the \"wasm\" feature".to_string(),
+	})
+}
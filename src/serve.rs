@@ -0,0 +1,188 @@
+use crate::{error::{Error, Result}, site::Site, watch::watch};
+use brotli::{CompressorWriter, Decompressor};
+use std::{fs, io::{Read, Write}, path::{Component, Path, PathBuf}, sync::{Arc, atomic::{AtomicUsize, Ordering}}, thread};
+use tiny_http::{Header, Request, Response, Server};
+use urlencoding::decode;
+
+const RELOAD_PATH: &str = "/__katsite/reload";
+
+const RELOAD_SNIPPET: &str = "<script>(function(){var g=null;setInterval(function(){fetch(\"/__katsite/reload\").then(function(r){return r.text()}).then(function(t){if(g!==null&&g!==t){location.reload()}g=t}).catch(function(){})},1000)})()</script>";
+
+const WORKERS: usize = 4;
+
+fn content_type(path: &Path) -> &'static str {
+	match path.extension().and_then(|extension| extension.to_str()).unwrap_or("") {
+		"html" | "htm" => "text/html; charset=utf-8",
+		"css" => "text/css; charset=utf-8",
+		"js" => "application/javascript; charset=utf-8",
+		"json" => "application/json",
+		"xml" => "application/xml; charset=utf-8",
+		"txt" => "text/plain; charset=utf-8",
+		"svg" => "image/svg+xml",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"webp" => "image/webp",
+		"ico" => "image/x-icon",
+		"woff2" => "font/woff2",
+		"wasm" => "application/wasm",
+		_ => "application/octet-stream",
+	}
+}
+
+fn header(field: &str, value: &str) -> Header {
+	Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap_or_else(|()| unreachable!("Invalid {} header", field))
+}
+
+fn resolve(output_dir: &Path, url: &str) -> Option<PathBuf> {
+	let path = url.split(['?', '#']).next().unwrap_or("");
+	let path = decode(path).ok()?;
+
+	let mut resolved = output_dir.to_path_buf();
+	for component in Path::new(path.trim_start_matches('/')).components() {
+		match component {
+			Component::Normal(part) => resolved.push(part),
+			Component::CurDir => (),
+			_ => return None,
+		}
+	}
+
+	if resolved.is_dir() {
+		resolved.push("index.html");
+	}
+	if !resolved.is_file() && resolved.extension().is_none() {
+		resolved.set_extension("html");
+	}
+	if resolved.is_file() {
+		Some(resolved)
+	} else {
+		None
+	}
+}
+
+fn compressed_sibling(path: &Path) -> PathBuf {
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(".br");
+	path.with_file_name(name)
+}
+
+fn read_source(path: &Path) -> Option<Vec<u8>> {
+	let compressed = compressed_sibling(path);
+	if compressed.is_file() {
+		let mut output = Vec::new();
+		let file = fs::File::open(&compressed).ok()?;
+		if Decompressor::new(file, 4096).read_to_end(&mut output).is_ok() {
+			return Some(output)
+		}
+		eprintln!("Warn: Unable to decompress {:#?}, serving the uncompressed file instead!", compressed);
+	}
+	fs::read(path).ok()
+}
+
+fn compress(input: &[u8]) -> Vec<u8> {
+	let mut output = Vec::new();
+	{
+		let mut writer = CompressorWriter::new(&mut output, 4096, 5, 22);
+		let _ = writer.write_all(input);
+	}
+	output
+}
+
+fn respond(request: Request, output_dir: &Path, url_stub: Option<&str>, local_url: &str, generation: &AtomicUsize) {
+	if request.url() == RELOAD_PATH {
+		let response = Response::from_string(generation.load(Ordering::SeqCst).to_string())
+			.with_header(header("Cache-Control", "no-store"));
+		let _ = request.respond(response);
+		return
+	}
+
+	let path = if let Some(path) = resolve(output_dir, request.url()) {
+		path
+	} else {
+		println!("404 {}", request.url());
+		let _ = request.respond(Response::from_string("404 Not Found").with_status_code(404));
+		return
+	};
+
+	let accepts_br = request.headers().iter().any(|header| {
+		header.field.equiv("Accept-Encoding") && header.value.as_str().split(',').any(|encoding| {
+			encoding.trim().split(';').next() == Some("br")
+		})
+	});
+	let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+	let is_html = extension == "html" || extension == "htm";
+	let rewrite = is_html || extension == "xml" || extension == "txt";
+
+	let (body, encoded) = if rewrite {
+		let mut body = String::from_utf8_lossy(&read_source(&path).unwrap_or_default()).to_string();
+		if let Some(url_stub) = url_stub {
+			body = body.replace(url_stub, local_url);
+		}
+		if is_html {
+			if let Some(index) = body.rfind("</body>") {
+				body.insert_str(index, RELOAD_SNIPPET);
+			} else {
+				body.push_str(RELOAD_SNIPPET);
+			}
+		}
+		if accepts_br {
+			(compress(body.as_bytes()), true)
+		} else {
+			(body.into_bytes(), false)
+		}
+	} else {
+		let compressed = compressed_sibling(&path);
+		if accepts_br && compressed.is_file() {
+			(fs::read(&compressed).unwrap_or_default(), true)
+		} else {
+			(fs::read(&path).unwrap_or_default(), false)
+		}
+	};
+
+	println!("200 {}{}", request.url(), if encoded { " (br)" } else { "" });
+	let mut response = Response::from_data(body)
+		.with_header(header("Content-Type", content_type(&path)))
+		.with_header(header("Cache-Control", "no-cache"))
+		.with_header(header("Vary", "Accept-Encoding"));
+	if encoded {
+		response = response.with_header(header("Content-Encoding", "br"));
+	}
+	let _ = request.respond(response);
+}
+
+/// Serves the site's output directory, rebuilding it on changes and reloading any open pages afterwards.
+///
+/// This only returns if the server or file watcher can't be started.
+///
+/// # Errors
+///
+/// Returns [`Error::Serve`] if the server can't listen on the configured address, or [`Error::Watch`] if watching fails.
+pub fn serve(site: &mut Site) -> Result<()> {
+	let config = site.config();
+	let server = Arc::new(Server::http(&config.serve.address).map_err(|err| Error::Serve {
+		address: config.serve.address.to_owned(),
+		message: err.to_string(),
+	})?);
+	let local_url = ["http://", &config.serve.address].concat();
+	let url_stub = config.url_stub().map(ToString::to_string);
+	let output_dir = config.files.output_dir.to_owned();
+	let generation = Arc::new(AtomicUsize::new(0));
+
+	for _ in 0..WORKERS {
+		let server = Arc::clone(&server);
+		let output_dir = output_dir.to_owned();
+		let url_stub = url_stub.to_owned();
+		let local_url = local_url.to_owned();
+		let generation = Arc::clone(&generation);
+		thread::spawn(move || {
+			while let Ok(request) = server.recv() {
+				respond(request, &output_dir, url_stub.as_deref(), &local_url, &generation);
+			}
+		});
+	}
+
+	println!("Serving {} at {}", output_dir.to_string_lossy(), local_url);
+	watch(site, &|| {
+		generation.fetch_add(1, Ordering::SeqCst);
+	})
+}
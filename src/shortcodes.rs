@@ -0,0 +1,404 @@
+//! Shortcodes, which expand `{{< name key="value" >}}` in Markdown files using Liquid templates from `shortcodes/<name>.liquid`.
+//!
+//! Shortcodes can also wrap content, like `{{< callout type="warning" >}}Careful!{{< /callout >}}`. Templates receive their arguments in `args`, and the wrapped content in `content`, with any shortcodes inside it already expanded. As the content is Markdown, templates that wrap it in HTML should leave a blank line on either side of it, so that it's still rendered. A shortcode that's never closed doesn't wrap anything, and `{{< name />}}` never does.
+//!
+//! Shortcodes are expanded before the Markdown is rendered. `{{</* name */>}}` is written out as `{{< name >}}`, for documenting shortcodes.
+
+use crate::{error::{Error, Result}, frontmatter};
+use liquid::{model::Value, Object, ParserBuilder, Template};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Where shortcode templates are loaded from.
+pub const SHORTCODE_DIR: &str = "shortcodes/";
+
+const OPEN: &str = "{{<";
+const CLOSE: &str = ">}}";
+const ESCAPED_OPEN: &str = "{{</*";
+const ESCAPED_CLOSE: &str = "*/>}}";
+
+struct Tag {
+	name: String,
+	args: Vec<(String, String)>,
+	// Written as `{{< name />}}`, so it can't wrap content.
+	self_closing: bool,
+}
+
+enum Parsed {
+	Open(Tag),
+	Close(String),
+}
+
+enum Token<'a> {
+	Text(&'a str),
+	Open(Tag, usize),
+	Close(String, usize),
+}
+
+enum Node<'a> {
+	Text(&'a str),
+	Shortcode {
+		tag: Tag,
+		line: usize,
+		content: Option<Vec<Self>>,
+	},
+}
+
+// An open shortcode that hasn't been closed yet, or the whole file.
+struct Frame<'a> {
+	open: Option<(Tag, usize)>,
+	nodes: Vec<Node<'a>>,
+}
+
+fn valid_name(name: &str) -> bool {
+	!name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+// Parses an argument's value, which is either quoted or ends at the next space, returning it and the rest of the shortcode.
+fn parse_value(input: &str) -> Option<(String, &str)> {
+	let quote = if let Some(quote) = input.chars().next().filter(|c| *c == '"' || *c == '\'') {
+		quote
+	} else {
+		let end = input.find(char::is_whitespace).unwrap_or(input.len());
+		return Some((input[..end].to_string(), &input[end..]))
+	};
+	let mut value = String::new();
+	let mut chars = input.char_indices().skip(1);
+	while let Some((index, c)) = chars.next() {
+		match c {
+			'\\' => if let Some((_, escaped)) = chars.next() {
+				value.push(escaped);
+			},
+			c if c == quote => return Some((value, &input[index + 1..])),
+			c => value.push(c),
+		}
+	}
+	None
+}
+
+// Parses what's between `{{<` and `>}}`.
+fn parse_tag(tag: &str) -> std::result::Result<Parsed, String> {
+	let tag = tag.trim();
+	if let Some(name) = tag.strip_prefix('/') {
+		let name = name.trim();
+		if !valid_name(name) {
+			return Err(format!("invalid shortcode name {:#?}", name))
+		}
+		return Ok(Parsed::Close(name.to_string()))
+	}
+	let (tag, self_closing) = tag.strip_suffix('/').map_or((tag, false), |tag| (tag.trim_end(), true));
+
+	let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+	let name = &tag[..name_end];
+	if !valid_name(name) {
+		return Err(format!("invalid shortcode name {:#?}", name))
+	}
+
+	let mut args = Vec::new();
+	let mut rest = tag[name_end..].trim_start();
+	while !rest.is_empty() {
+		let key_end = rest.find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_')).unwrap_or(rest.len());
+		let key = &rest[..key_end];
+		if key.is_empty() {
+			return Err(format!("unexpected {:#?} in shortcode {}", rest, name))
+		}
+		rest = rest[key_end..].strip_prefix('=').ok_or_else(|| format!("argument {} of shortcode {} needs a value, like {}=\"...\"", key, name, key))?;
+
+		let (value, after) = parse_value(rest).ok_or_else(|| format!("argument {} of shortcode {} is missing its closing quote", key, name))?;
+		rest = after;
+		args.push((key.to_string(), value));
+		rest = rest.trim_start();
+	}
+
+	Ok(Parsed::Open(Tag {
+		name: name.to_string(),
+		args,
+		self_closing,
+	}))
+}
+
+// Finds the `>}}` that ends a shortcode, ignoring any inside quoted arguments.
+fn tag_end(input: &str) -> Option<usize> {
+	let mut quote = None;
+	let mut escaped = false;
+	for (index, c) in input.char_indices() {
+		match (quote, c) {
+			(Some(_), '\\') if !escaped => {
+				escaped = true;
+				continue
+			},
+			(Some(open), c) if c == open && !escaped => quote = None,
+			(None, '"' | '\'') => quote = Some(c),
+			(None, '>') if input[index..].starts_with(CLOSE) => return Some(index),
+			_ => {},
+		}
+		escaped = false;
+	}
+	None
+}
+
+fn tokenize<'a>(body: &'a str, lines_before: usize, errors: &mut Vec<(usize, String)>) -> Vec<Token<'a>> {
+	let line = |position: usize| lines_before + body[..position].matches('\n').count() + 1;
+	let mut tokens = Vec::new();
+	let mut position = 0;
+	while let Some(start) = body[position..].find(OPEN).map(|start| position + start) {
+		tokens.push(Token::Text(&body[position..start]));
+		if body[start..].starts_with(ESCAPED_OPEN) {
+			if let Some(end) = body[start..].find(ESCAPED_CLOSE) {
+				tokens.push(Token::Text(OPEN));
+				tokens.push(Token::Text(&body[start + ESCAPED_OPEN.len()..start + end]));
+				tokens.push(Token::Text(CLOSE));
+				position = start + end + ESCAPED_CLOSE.len();
+				continue
+			}
+		}
+
+		let inner = start + OPEN.len();
+		// An unclosed quote is reported by `parse_tag`.
+		let end = if let Some(end) = tag_end(&body[inner..]).or_else(|| body[inner..].find(CLOSE)) {
+			inner + end
+		} else {
+			errors.push((line(start), format!("shortcode is missing its closing {}", CLOSE)));
+			return tokens
+		};
+		match parse_tag(&body[inner..end]) {
+			Ok(Parsed::Open(tag)) => tokens.push(Token::Open(tag, line(start))),
+			Ok(Parsed::Close(name)) => tokens.push(Token::Close(name, line(start))),
+			Err(message) => errors.push((line(start), message)),
+		}
+		position = end + CLOSE.len();
+	}
+	tokens.push(Token::Text(&body[position..]));
+	tokens
+}
+
+// Closes the innermost open shortcode without any content, as it turned out not to wrap anything.
+fn unwind(stack: &mut Vec<Frame>) {
+	if stack.len() < 2 {
+		return
+	}
+	if let (Some(frame), Some(parent)) = (stack.pop(), stack.last_mut()) {
+		if let Some((tag, line)) = frame.open {
+			parent.nodes.push(Node::Shortcode {
+				tag,
+				line,
+				content: None,
+			});
+		}
+		parent.nodes.extend(frame.nodes);
+	}
+}
+
+// Pairs up opening and closing shortcodes.
+fn build_tree<'a>(tokens: Vec<Token<'a>>, errors: &mut Vec<(usize, String)>) -> Vec<Node<'a>> {
+	let mut stack = vec![Frame {
+		open: None,
+		nodes: Vec::new(),
+	}];
+	for token in tokens {
+		match token {
+			Token::Text(text) => if let Some(frame) = stack.last_mut() {
+				frame.nodes.push(Node::Text(text));
+			},
+			Token::Open(tag, line) if tag.self_closing => if let Some(frame) = stack.last_mut() {
+				frame.nodes.push(Node::Shortcode {
+					tag,
+					line,
+					content: None,
+				});
+			},
+			Token::Open(tag, line) => stack.push(Frame {
+				open: Some((tag, line)),
+				nodes: Vec::new(),
+			}),
+			Token::Close(name, line) => {
+				let opening = stack.iter().rposition(|frame| frame.open.as_ref().is_some_and(|(tag, _)| tag.name == name));
+				if let Some(opening) = opening {
+					while stack.len() > opening + 1 {
+						unwind(&mut stack);
+					}
+					if let Some(Frame { open: Some((tag, open_line)), nodes }) = stack.pop() {
+						if let Some(parent) = stack.last_mut() {
+							parent.nodes.push(Node::Shortcode {
+								tag,
+								line: open_line,
+								content: Some(nodes),
+							});
+						}
+					}
+				} else {
+					errors.push((line, format!("{} /{} {} doesn't close any shortcode", OPEN, name, CLOSE)));
+				}
+			},
+		}
+	}
+	while stack.len() > 1 {
+		unwind(&mut stack);
+	}
+	stack.pop().map(|frame| frame.nodes).unwrap_or_default()
+}
+
+/// Every shortcode template, parsed once per build.
+pub struct Shortcodes {
+	// Templates that fail to parse are only reported when they're used.
+	templates: HashMap<String, std::result::Result<Template, String>>,
+}
+
+impl Shortcodes {
+	/// Loads every template in the shortcodes directory, if there is one.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the directory or a template can't be read.
+	pub fn load() -> Result<Self> {
+		let mut templates = HashMap::new();
+		let dir = Path::new(SHORTCODE_DIR);
+		if !dir.is_dir() {
+			return Ok(Self {
+				templates,
+			})
+		}
+
+		let parser = ParserBuilder::with_stdlib().build().map_err(|err| Error::Shortcode(vec![err.to_string()]))?;
+		for entry in fs::read_dir(dir).map_err(Error::io(dir))? {
+			let path = entry.map_err(Error::io(dir))?.path();
+			if path.extension().is_none_or(|extension| extension != "liquid") {
+				continue
+			}
+			let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+			let source = fs::read_to_string(&path).map_err(Error::io(&path))?;
+			templates.insert(name, parser.parse(&source).map_err(|err| err.to_string().trim_end().to_string()));
+		}
+		Ok(Self {
+			templates,
+		})
+	}
+
+	fn render(&self, nodes: &[Node], output: &mut String, errors: &mut Vec<(usize, String)>) {
+		for node in nodes {
+			let (tag, line, content) = match node {
+				Node::Text(text) => {
+					output.push_str(text);
+					continue
+				},
+				Node::Shortcode { tag, line, content } => (tag, line, content),
+			};
+
+			let mut inner = String::new();
+			if let Some(content) = content {
+				self.render(content, &mut inner, errors);
+			}
+			let template = match self.templates.get(&tag.name) {
+				Some(Ok(template)) => template,
+				Some(Err(err)) => {
+					errors.push((*line, format!("shortcode {} is invalid! {}", tag.name, err)));
+					continue
+				},
+				None => {
+					errors.push((*line, format!("unknown shortcode {}, there's no {}{}.liquid", tag.name, SHORTCODE_DIR, tag.name)));
+					continue
+				},
+			};
+
+			let mut args = Object::new();
+			for (key, value) in &tag.args {
+				args.insert(key.to_owned().into(), Value::scalar(value.to_owned()));
+			}
+			let globals = liquid::object!({
+				"args": args,
+				"content": inner,
+			});
+			match template.render(&globals) {
+				Ok(rendered) => output.push_str(&rendered),
+				Err(err) => errors.push((*line, format!("unable to render shortcode {}! {}", tag.name, err))),
+			}
+		}
+	}
+
+	/// Expands every shortcode in a Markdown file. The frontmatter is left as it is.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Shortcode`] listing every invalid or unknown shortcode, along with the line it's on.
+	pub fn expand(&self, input: &str) -> Result<String> {
+		if !input.contains(OPEN) {
+			return Ok(input.to_string())
+		}
+		let (frontmatter, body) = frontmatter::split(input);
+		let mut errors = Vec::new();
+		let tokens = tokenize(body, frontmatter.matches('\n').count(), &mut errors);
+		let tree = build_tree(tokens, &mut errors);
+		let mut output = frontmatter.to_string();
+		self.render(&tree, &mut output, &mut errors);
+		if errors.is_empty() {
+			return Ok(output)
+		}
+		errors.sort_by_key(|(line, _)| *line);
+		Err(Error::Shortcode(errors.into_iter().map(|(line, message)| format!("line {}: {}", line, message)).collect()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn shortcodes(templates: &[(&str, &str)]) -> Shortcodes {
+		let parser = ParserBuilder::with_stdlib().build().unwrap();
+		Shortcodes {
+			templates: templates.iter().map(|(name, source)| ((*name).to_string(), parser.parse(source).map_err(|err| err.to_string()))).collect(),
+		}
+	}
+
+	fn errors(result: Result<String>) -> Vec<String> {
+		match result {
+			Err(Error::Shortcode(messages)) => messages,
+			result => panic!("expected shortcode errors, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn escaped_shortcodes_are_written_out() {
+		let shortcodes = shortcodes(&[]);
+		assert_eq!(shortcodes.expand("Use {{</* callout type=\"note\" */>}} here.").unwrap(), "Use {{< callout type=\"note\" >}} here.");
+	}
+
+	#[test]
+	fn quoted_arguments_can_contain_the_closing_delimiter() {
+		let shortcodes = shortcodes(&[("quote", "<q>{{ args.text }}</q>")]);
+		assert_eq!(shortcodes.expand("{{< quote text=\"a >}} b\" >}}").unwrap(), "<q>a >}} b</q>");
+		assert_eq!(shortcodes.expand("{{< quote text='it\\'s >}}' >}}").unwrap(), "<q>it's >}}</q>");
+	}
+
+	#[test]
+	fn shortcodes_wrap_content_until_they_are_closed() {
+		let shortcodes = shortcodes(&[("box", "[{{ content }}]")]);
+		assert_eq!(shortcodes.expand("{{< box >}}a{{< box >}}b{{< /box >}}c{{< /box >}}").unwrap(), "[a[b]c]");
+		// A self-closing shortcode never wraps anything, even if it's followed by a closing tag of the same name.
+		assert_eq!(shortcodes.expand("{{< box />}}a{{< box >}}b{{< /box >}}").unwrap(), "[]a[b]");
+	}
+
+	#[test]
+	fn unclosed_shortcodes_are_unwound() {
+		let shortcodes = shortcodes(&[("box", "[{{ content }}]"), ("note", "({{ content }})")]);
+		assert_eq!(shortcodes.expand("{{< box >}}a").unwrap(), "[]a");
+		// The closing tag skips over the unclosed shortcode inside of it.
+		assert_eq!(shortcodes.expand("{{< box >}}a{{< note >}}b{{< /box >}}c").unwrap(), "[a()b]c");
+	}
+
+	#[test]
+	fn stray_closing_shortcodes_are_errors() {
+		let shortcodes = shortcodes(&[("box", "[{{ content }}]")]);
+		assert_eq!(errors(shortcodes.expand("a\n{{< /box >}}\n")), vec!["line 2: {{< /box >}} doesn't close any shortcode"]);
+		assert_eq!(errors(shortcodes.expand("{{< box />}}\n{{< /box >}}")), vec!["line 2: {{< /box >}} doesn't close any shortcode"]);
+	}
+
+	#[test]
+	fn errors_are_reported_on_the_line_in_the_file() {
+		let shortcodes = shortcodes(&[("box", "[{{ content }}]")]);
+		let input = "+++\ntitle = \"Hi\"\n+++\n# Hi\n\n{{< missing >}}\n{{< box\nkey=\"x\" >}}{{< box key >}}\n{{< /other >}}\n";
+		assert_eq!(errors(shortcodes.expand(input)), vec![
+			"line 6: unknown shortcode missing, there's no shortcodes/missing.liquid",
+			"line 8: argument key of shortcode box needs a value, like key=\"...\"",
+			"line 9: {{< /other >}} doesn't close any shortcode",
+		]);
+	}
+}
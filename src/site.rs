@@ -0,0 +1,739 @@
+use crate::{cache::{build_key, hash_to_string, load_cache, save_cache, CACHE_DIR}, config::{CheckLinks, Config, FailurePolicy, Highlight, CONFIG_FILE}, error::{Error, Result}, frontmatter, highlight, linkcheck::check_links, links::Pages, markdown::markdown_to_html, plugins::{init_plugins, resolve_order, run_plugins, wasm_path, PluginEnv, PLUGIN_DIR}, registry::{Constructor, Registry}, protocol::{Metadata, PageInfo}, shortcodes::Shortcodes, toc};
+use glob::glob;
+use rayon::prelude::*;
+use std::{fs, collections::HashMap, hash::Hasher, path::{Component, Path, PathBuf}, sync::Arc, thread, time::{Duration, Instant}};
+
+/// How much progress output to print.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Verbosity {
+	/// Only print warnings and errors.
+	Quiet,
+	/// Print each file as it is processed.
+	#[default]
+	Normal,
+	/// Also print files that are skipped.
+	Verbose,
+}
+
+impl Verbosity {
+	#[must_use]
+	pub const fn as_str(self) -> &'static str {
+		match self {
+			Self::Quiet => "quiet",
+			Self::Normal => "normal",
+			Self::Verbose => "verbose",
+		}
+	}
+}
+
+/// Which parts of the build pipeline to run.
+pub struct Stages {
+	/// Run the `preinit` plugin hook, which can generate input files before they're found.
+	pub preinit: bool,
+	/// Convert Markdown files to HTML.
+	pub markdown: bool,
+	/// Only convert these files, instead of everything matched by the input glob.
+	pub markdown_files: Option<Vec<PathBuf>>,
+	/// Run the `asyncinit` plugin hook, which usually builds assets.
+	pub asyncinit: bool,
+	/// Run the `postinit` plugin hook, which usually applies templates.
+	pub postinit: bool,
+}
+
+impl Stages {
+	/// Every stage, for every file.
+	#[must_use]
+	pub const fn all() -> Self {
+		Self {
+			preinit: true,
+			markdown: true,
+			markdown_files: None,
+			asyncinit: true,
+			postinit: true,
+		}
+	}
+
+	/// No stages, to be enabled one at a time.
+	#[must_use]
+	pub const fn none() -> Self {
+		Self {
+			preinit: false,
+			markdown: false,
+			markdown_files: Some(Vec::new()),
+			asyncinit: false,
+			postinit: false,
+		}
+	}
+}
+
+/// A summary of what a build did.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+	/// Input files that were converted to HTML.
+	pub converted: Vec<PathBuf>,
+	/// Input files that were skipped, as they were unchanged since the last build, or a plugin with the `skip` failure policy failed on them.
+	pub skipped: Vec<PathBuf>,
+	/// Input files that weren't built, as they're drafts, scheduled for later, or expired.
+	pub unpublished: Vec<PathBuf>,
+	/// How long the build took.
+	pub duration: Duration,
+}
+
+/// Creates a [`Site`], loading its config from disk unless one is given.
+#[derive(Default)]
+pub struct Builder {
+	config: Option<Config>,
+	config_path: Option<PathBuf>,
+	overrides: Vec<String>,
+	verbosity: Verbosity,
+	registry: Registry,
+}
+
+impl Builder {
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Uses an already loaded config, instead of reading one from disk.
+	#[must_use]
+	pub fn config(mut self, config: Config) -> Self {
+		self.config = Some(config);
+		self
+	}
+
+	/// Reads the config from `path`, instead of `conf.toml`.
+	#[must_use]
+	pub fn config_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.config_path = Some(path.into());
+		self
+	}
+
+	/// Overrides a setting in the config, given as `key=value` (see [`Config::set_str`]).
+	#[must_use]
+	pub fn set<S: Into<String>>(mut self, setting: S) -> Self {
+		self.overrides.push(setting.into());
+		self
+	}
+
+	/// Overrides the output directory.
+	#[must_use]
+	pub fn output_dir<P: AsRef<Path>>(self, path: P) -> Self {
+		let path = toml::Value::String(path.as_ref().to_string_lossy().to_string());
+		self.set(["files.output_dir=", &path.to_string()].concat())
+	}
+
+	/// Overrides the pattern used to find input files.
+	#[must_use]
+	pub fn input_glob(self, pattern: &str) -> Self {
+		let pattern = toml::Value::String(pattern.to_string());
+		self.set(["files.input_glob=", &pattern.to_string()].concat())
+	}
+
+	#[must_use]
+	pub const fn verbosity(mut self, verbosity: Verbosity) -> Self {
+		self.verbosity = verbosity;
+		self
+	}
+
+	/// Adds a built-in plugin, which runs in-process when it's listed in `plugins_list`.
+	#[must_use]
+	pub fn plugin(mut self, name: &str, constructor: Constructor) -> Self {
+		self.registry.register(name, constructor);
+		self
+	}
+
+	/// Uses a different set of built-in plugins, instead of the ones enabled by cargo features.
+	#[must_use]
+	pub fn registry(mut self, registry: Registry) -> Self {
+		self.registry = registry;
+		self
+	}
+
+	/// Loads the config if needed, applies any overrides, and creates the site.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the config file can't be read or parsed, or an override can't be applied.
+	pub fn finish(self) -> Result<Site> {
+		let config_path = self.config_path.unwrap_or_else(|| PathBuf::from(CONFIG_FILE));
+		let config = if let Some(config) = self.config {
+			config
+		} else {
+			Config::load(&config_path)?
+		};
+		let mut site = Site {
+			config,
+			config_path,
+			overrides: self.overrides,
+			verbosity: self.verbosity,
+			registry: self.registry,
+		};
+		site.apply_overrides()?;
+		Ok(site)
+	}
+}
+
+/// A site that can be built.
+pub struct Site {
+	config: Config,
+	config_path: PathBuf,
+	overrides: Vec<String>,
+	verbosity: Verbosity,
+	registry: Registry,
+}
+
+impl Site {
+	/// Creates a site from an already loaded config.
+	#[must_use]
+	pub fn new(config: Config) -> Self {
+		Self {
+			config,
+			config_path: PathBuf::from(CONFIG_FILE),
+			overrides: Vec::new(),
+			verbosity: Verbosity::default(),
+			registry: Registry::default(),
+		}
+	}
+
+	#[must_use]
+	pub const fn verbosity(&self) -> Verbosity {
+		self.verbosity
+	}
+
+	#[must_use]
+	pub const fn config(&self) -> &Config {
+		&self.config
+	}
+
+	/// The config file that this site's config was loaded from, and should be reloaded from when it changes.
+	#[must_use]
+	pub fn config_path(&self) -> &Path {
+		&self.config_path
+	}
+
+	/// Reloads the config from disk, reapplying any overrides.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the config file can't be read or parsed. The current config is kept if this happens.
+	pub fn reload_config(&mut self) -> Result<()> {
+		let mut config = Config::load(&self.config_path)?;
+		for setting in &self.overrides {
+			config.set_str(setting)?;
+		}
+		self.config = config;
+		Ok(())
+	}
+
+	fn apply_overrides(&mut self) -> Result<()> {
+		for setting in &self.overrides {
+			self.config.set_str(setting)?;
+		}
+		Ok(())
+	}
+
+	/// The environment that plugins are started with. If the config didn't come straight from a file, it is written to the build cache so plugins can read it.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Io`] if the config can't be written, [`Error::Manifest`] if a plugin's manifest is invalid, or whatever error a built-in plugin fails to load with.
+	pub fn plugin_env(&self) -> Result<PluginEnv> {
+		let config_path = if let Some(source) = self.config.source() {
+			source.to_path_buf()
+		} else {
+			let path = self.config.files.output_dir.join(CACHE_DIR).join("config.toml");
+			if let Some(parent) = path.parent() {
+				fs::create_dir_all(parent).map_err(Error::io(parent))?;
+			}
+			fs::write(&path, self.config.raw.to_string()).map_err(Error::io(&path))?;
+			path
+		};
+		let plugins = self.config.plugins_list.iter().map(|plugin| {
+			self.registry.load(plugin, &self.config)
+		}).collect::<Result<_>>()?;
+		Ok(PluginEnv::new(config_path, &self.config, self.verbosity, resolve_order(plugins)?))
+	}
+
+	/// Every page in the site, along with its frontmatter, as sent to plugins.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the input glob can't be parsed. Files that can't be read, and pages that aren't published, are left out.
+	pub fn pages(&self, metadata: &HashMap<PathBuf, Metadata>) -> Result<Vec<PageInfo>> {
+		let config = &self.config;
+		let mut files: Vec<PathBuf> = glob(&config.files.input_glob)?.filter_map(std::result::Result::ok).filter(|source| inside_site(source)).collect();
+		files.sort();
+
+		Ok(files.into_par_iter().filter_map(|source| {
+			let input = fs::read_to_string(&source).ok()?;
+			// Invalid frontmatter is reported when the page itself is built.
+			let frontmatter = frontmatter::parse(&input).unwrap_or_else(|_| toml::Value::Table(toml::value::Table::new()));
+			if !matches!(frontmatter::unpublished(&frontmatter, &config.files), Ok(None)) {
+				return None
+			}
+			Some(PageInfo {
+				output: output_path(&config.files.output_dir, &source),
+				metadata: metadata.get(&source).cloned().unwrap_or_default(),
+				source,
+				frontmatter,
+			})
+		}).collect())
+	}
+
+	/// Checks the config, input files and plugins for problems, without building anything.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the input glob can't be parsed. Other problems are returned as a list of messages.
+	pub fn check(&self) -> Result<Vec<String>> {
+		let config = &self.config;
+		let mut problems = Vec::new();
+
+		let files: Vec<_> = glob(&config.files.input_glob)?.collect();
+		if files.is_empty() {
+			problems.push(format!("No input files match {:#?}.", config.files.input_glob));
+		}
+		let mut outputs = HashMap::new();
+		for file in files {
+			match file {
+				Ok(path) if !inside_site(&path) => problems.push(format!("{:#?} is outside of the site's directory, so it can't be built.", path)),
+				Ok(path) => {
+					let output = output_path(&config.files.output_dir, &path);
+					if let Some(other) = outputs.insert(output.to_owned(), path.to_owned()) {
+						problems.push(format!("{:#?} and {:#?} are both built to {:#?}.", other, path, output));
+					}
+					if let Err(err) = fs::read(&path) {
+						problems.push(format!("Unable to read {:#?}: {}", path, err));
+					}
+				},
+				Err(err) => problems.push(format!("Unable to read {:#?}: {}", err.path(), err.error())),
+			}
+		}
+		for option in config.markdown.unsupported() {
+			problems.push(format!("Markdown option {} is not supported by the {} backend.", option, config.markdown.backend().as_str()));
+		}
+
+		for plugin in &config.plugins_list {
+			let path = Path::new(PLUGIN_DIR).join(plugin);
+			if !self.registry.is_builtin(plugin) && !path.is_file() && !wasm_path(plugin).is_file() {
+				problems.push(format!("Plugin {} is not installed in {:#?}.", plugin, PLUGIN_DIR));
+			}
+		}
+		let plugins = config.plugins_list.iter().filter_map(|plugin| {
+			self.registry.load(plugin, config).map_err(|err| problems.push(err.to_string())).ok()
+		}).collect();
+		if let Err(err) = resolve_order(plugins) {
+			problems.push(err.to_string());
+		}
+
+		Ok(problems)
+	}
+
+	/// Checks every link in the output directory, without building anything.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the input glob can't be parsed, or the output directory can't be read. Broken links are returned as an [`Error::File`] for every page that has them, against the input file it was built from if there is one.
+	pub fn check_links(&self) -> Result<Vec<Error>> {
+		self.log(Verbosity::Normal, "Checking links...");
+		let sources = glob(&self.config.files.input_glob)?.filter_map(std::result::Result::ok).filter(|input| inside_site(input)).map(|input| {
+			(relative_path(&input).with_extension("html"), input)
+		}).collect();
+		check_links(&self.config.files.output_dir, self.config.url_stub(), &sources)
+	}
+
+	/// Removes everything that a build creates. If the output directory also holds the site's sources, only the build cache and the HTML generated from input files are removed.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the input glob can't be parsed, or files can't be removed.
+	pub fn clean(&self) -> Result<()> {
+		let output_dir = &self.config.files.output_dir;
+		if !output_dir.exists() {
+			return Ok(())
+		}
+
+		let canonical = output_dir.canonicalize().map_err(Error::io(output_dir))?;
+		let current = Path::new(".").canonicalize().map_err(Error::io("."))?;
+		if !current.starts_with(&canonical) {
+			self.log(Verbosity::Normal, &format!("Removing {}...", output_dir.to_string_lossy()));
+			return fs::remove_dir_all(output_dir).map_err(Error::io(output_dir))
+		}
+
+		for file in glob(&self.config.files.input_glob)?.filter_map(std::result::Result::ok).filter(|file| inside_site(file)) {
+			let output = output_path(output_dir, &file);
+			for path in &[output.to_owned(), output.with_extension("html.br")] {
+				if path.exists() {
+					self.log(Verbosity::Normal, &format!("Removing {}...", path.to_string_lossy()));
+					fs::remove_file(path).map_err(Error::io(path))?;
+				}
+			}
+		}
+		let cache = output_dir.join(CACHE_DIR);
+		if cache.exists() {
+			self.log(Verbosity::Normal, &format!("Removing {}...", cache.to_string_lossy()));
+			fs::remove_dir_all(&cache).map_err(Error::io(&cache))?;
+		}
+		Ok(())
+	}
+
+	fn log(&self, level: Verbosity, message: &str) {
+		if self.verbosity as u8 >= level as u8 {
+			println!("{}", message);
+		}
+	}
+
+	/// Runs the whole build pipeline.
+	///
+	/// # Errors
+	///
+	/// Returns every error encountered while building, combined into [`Error::Build`] if there was more than one. Files that fail to build are left out, and the rest of the site is still built.
+	pub fn build(&self) -> Result<BuildReport> {
+		self.build_stages(&Stages::all())
+	}
+
+	/// Runs part of the build pipeline.
+	///
+	/// # Errors
+	///
+	/// Returns every error encountered while building, combined into [`Error::Build`] if there was more than one. Files that fail to build are left out, and the rest of the site is still built.
+	pub fn build_stages(&self, stages: &Stages) -> Result<BuildReport> {
+		let start = Instant::now();
+		let config = &self.config;
+
+		fs::create_dir_all(&config.files.output_dir).map_err(Error::io(&config.files.output_dir))?;
+		let mut env = self.plugin_env()?;
+		// Nothing else is built if this fails, as the rest of the build may depend on the files it generates.
+		if stages.preinit {
+			join("preinit", init_plugins("preinit".to_string(), false, env.to_owned()))?;
+		}
+		let mut metadata = load_cache(config).metadata.into_iter().filter_map(|(source, metadata)| {
+			Some((PathBuf::from(source), serde_json::from_str(&metadata).ok()?))
+		}).collect();
+		if env.wants_pages() {
+			env.pages = Arc::new(self.pages(&metadata)?);
+		}
+
+		let child = if stages.asyncinit {
+			self.write_highlight_stylesheet()?;
+			Some(init_plugins("asyncinit".to_string(), false, env.to_owned()))
+		} else {
+			None
+		};
+
+		let mut errors = Vec::new();
+		let pages = if stages.markdown {
+			self.build_pages(stages.markdown_files.as_deref(), &env, &mut metadata, &mut errors)
+		} else {
+			Ok(BuildReport::default())
+		};
+
+		// Pages that failed to build are left out, but the rest of the site is still finished.
+		if stages.postinit && pages.is_ok() {
+			if env.wants_pages() {
+				env.pages = Arc::new(self.pages(&metadata)?);
+			}
+			if let Err(err) = join("postinit", init_plugins("postinit".to_string(), true, env)) {
+				errors.push(err);
+			}
+		}
+		if let Err(err) = child.map_or(Ok(()), |child| join("asyncinit", child)) {
+			errors.push(err);
+		}
+
+		// Pages that failed to build would show up as broken links too, so links are only checked once everything else succeeds.
+		if config.files.check_links != CheckLinks::Off && pages.is_ok() && errors.is_empty() {
+			match self.check_links() {
+				Ok(broken) if config.files.check_links == CheckLinks::Warn => for err in broken {
+					eprintln!("Warning: {}", err);
+				},
+				Ok(broken) => errors.extend(broken),
+				Err(err) => errors.push(err),
+			}
+		}
+
+		let mut report = match pages {
+			Ok(report) => report,
+			Err(err) => {
+				errors.insert(0, err);
+				BuildReport::default()
+			},
+		};
+		Error::collect(errors)?;
+
+		report.duration = start.elapsed();
+		Ok(report)
+	}
+
+	// This is written before the asyncinit hook, so that plugins building the site's stylesheet can include it.
+	fn write_highlight_stylesheet(&self) -> Result<()> {
+		let markdown = &self.config.markdown;
+		if markdown.highlight != Highlight::Classes {
+			return Ok(())
+		}
+		let path = self.config.files.output_dir.join(&markdown.highlight_stylesheet);
+		let stylesheet = highlight::stylesheet(&markdown.highlight_theme).unwrap_or_default();
+		if fs::read_to_string(&path).ok().as_ref() != Some(&stylesheet) {
+			self.log(Verbosity::Normal, &format!("Writing {}...", path.to_string_lossy()));
+			fs::write(&path, stylesheet).map_err(Error::io(&path))?;
+		}
+		Ok(())
+	}
+
+	fn build_pages(&self, only: Option<&[PathBuf]>, env: &PluginEnv, metadata: &mut HashMap<PathBuf, Metadata>, errors: &mut Vec<Error>) -> Result<BuildReport> {
+		let config = &self.config;
+		let files: Vec<PathBuf> = if let Some(only) = only {
+			only.to_vec()
+		} else {
+			glob(&config.files.input_glob)?.filter_map(|entry| {
+				entry.map_err(|err| errors.push(Error::io(err.path().to_path_buf())(err.into()))).ok()
+			}).collect()
+		};
+		let files = buildable(files, &config.files.output_dir, errors);
+
+		let mut cache = load_cache(config);
+		let mut key = build_key(config);
+		let shortcodes = Shortcodes::load()?;
+		// Every page is found even when only some are being converted, as they can link to any of them.
+		let pages = if config.markdown.wiki_links {
+			let pages = Pages::load(config)?;
+			pages.hash_into(&mut key);
+			pages
+		} else {
+			Pages::default()
+		};
+
+		let built: Vec<_> = files.par_iter().map(|fpath| {
+			let input_name = fpath.to_string_lossy().to_string();
+
+			let mut input = fs::read(fpath).map_err(Error::io(fpath))?;
+
+			let output_path = output_path(&config.files.output_dir, fpath);
+			let frontmatter = frontmatter::parse(&String::from_utf8_lossy(&input)).map_err(Error::Frontmatter)?;
+			if let Some(reason) = frontmatter::unpublished(&frontmatter, &config.files).map_err(Error::Frontmatter)? {
+				self.log(Verbosity::Verbose, &format!("Skipping {} ({})...", input_name, reason));
+				// It may have been published by an earlier build.
+				for path in &[output_path.to_owned(), output_path.with_extension("html.br")] {
+					if path.exists() {
+						fs::remove_file(path).map_err(Error::io(path))?;
+					}
+				}
+				return Ok((input_name, String::new(), Outcome::Unpublished))
+			}
+
+			let mut hasher = key.to_owned();
+			hasher.write(&input);
+			let input_hash = hash_to_string(&hasher);
+			if output_path.exists() && cache.pages.get(&input_name) == Some(&input_hash) {
+				self.log(Verbosity::Verbose, &format!("Skipping {} (unchanged)...", input_name));
+				return Ok((input_name, input_hash, Outcome::Unchanged))
+			}
+
+			self.log(Verbosity::Normal, &format!("Parsing {}...", input_name));
+			if let Some(parent) = output_path.parent() {
+				fs::create_dir_all(parent).map_err(Error::io(parent))?;
+			}
+			let page_metadata = parse_to_file(&mut input, fpath, &output_path, config, &shortcodes, &pages, env)?;
+
+			Ok((input_name, input_hash, Outcome::Converted(page_metadata)))
+		}).zip(&files).collect();
+
+		if only.is_none() {
+			cache.pages.clear();
+			cache.metadata.clear();
+		}
+		let mut report = BuildReport::default();
+		for (page, fpath) in built {
+			match page {
+				Ok((input_name, input_hash, outcome)) => {
+					match outcome {
+						Outcome::Converted(page_metadata) => {
+							report.converted.push(PathBuf::from(&input_name));
+							if page_metadata.is_empty() {
+								metadata.remove(fpath);
+							} else {
+								metadata.insert(fpath.to_owned(), page_metadata);
+							}
+						},
+						Outcome::Unchanged => report.skipped.push(PathBuf::from(&input_name)),
+						Outcome::Unpublished => {
+							report.unpublished.push(PathBuf::from(&input_name));
+							metadata.remove(fpath);
+							cache.pages.remove(&input_name);
+							cache.metadata.remove(&input_name);
+							continue
+						},
+					}
+					if let Some(page_metadata) = metadata.get(fpath) {
+						cache.metadata.insert(input_name.to_owned(), serde_json::Value::from(page_metadata.to_owned()).to_string());
+					} else {
+						cache.metadata.remove(&input_name);
+					}
+					cache.pages.insert(input_name, input_hash);
+				},
+				Err(err) => {
+					cache.pages.remove(&*fpath.to_string_lossy());
+					cache.metadata.remove(&*fpath.to_string_lossy());
+					if env.on_failure(&err) == FailurePolicy::Skip {
+						// The page's output from the last build, if there is one, is left in place.
+						eprintln!("Warning: Skipping {}: {}", fpath.to_string_lossy(), err);
+						report.skipped.push(fpath.to_owned());
+						continue
+					}
+					errors.push(Error::File {
+						path: fpath.to_owned(),
+						source: Box::new(err),
+					});
+				},
+			}
+		}
+		save_cache(config, &cache)?;
+
+		Ok(report)
+	}
+}
+
+// What happened to a page during a build.
+enum Outcome {
+	Converted(Metadata),
+	Unchanged,
+	Unpublished,
+}
+
+fn join(hook: &str, child: thread::JoinHandle<Result<()>>) -> Result<()> {
+	child.join().unwrap_or_else(|panic| {
+		let message = panic.downcast_ref::<&str>().map(|message| (*message).to_string())
+			.or_else(|| panic.downcast_ref::<String>().cloned())
+			.unwrap_or_else(|| "no message was given".to_string());
+		Err(Error::HookPanicked {
+			hook: hook.to_string(),
+			message,
+		})
+	})
+}
+
+fn parse_to_file(input: &mut Vec<u8>, input_path: &Path, output_path: &Path, config: &Config, shortcodes: &Shortcodes, pages: &Pages, env: &PluginEnv) -> Result<Metadata> {
+	let mut metadata = if config.plugins_list.is_empty() {
+		Metadata::new()
+	} else {
+		run_plugins(input, "markdown", input_path, output_path, None, env)?
+	};
+
+	// The page is rendered in memory first, so a failure never leaves a half-written file behind.
+	let mut output = Vec::new();
+	let input = String::from_utf8_lossy(input);
+	if config.plugins_list.is_empty() {
+		output.extend_from_slice(b"<!doctype html><meta name=viewport content=\"width=device-width,initial-scale=1\">");
+	}
+	let mut markdown = shortcodes.expand(&input)?;
+	if config.markdown.wiki_links {
+		markdown = pages.replace_links(&markdown, input_path, config)?;
+	}
+	let rendered = markdown_to_html(&markdown, &config.markdown)?;
+	output.extend_from_slice(rendered.html.as_bytes());
+	if !config.plugins_list.is_empty() {
+		// The `markdown` hook may have changed the frontmatter.
+		let frontmatter = frontmatter::parse(&input).map_err(Error::Frontmatter)?;
+		metadata.extend(run_plugins(&mut output, "html", input_path, output_path, Some(&frontmatter), env)?);
+		if frontmatter.as_table().is_some_and(|table| !table.is_empty()) {
+			output.extend_from_slice(frontmatter::comment(&frontmatter).as_bytes());
+		}
+	}
+	if config.markdown.toc {
+		output.extend_from_slice(toc::comment(&rendered.toc).as_bytes());
+	}
+	fs::write(output_path, output).map_err(Error::io(output_path))?;
+	Ok(metadata)
+}
+
+/// Where the HTML for an input file is written, mirroring its location relative to the site's directory.
+#[must_use]
+pub fn output_path(output_dir: &Path, input: &Path) -> PathBuf {
+	output_dir.join(relative_path(input).with_extension("html"))
+}
+
+// Leaves out input files that can't be built, reporting why. Files outside of the site's directory, or built to the same place as an earlier file, would overwrite other files.
+fn buildable(files: Vec<PathBuf>, output_dir: &Path, errors: &mut Vec<Error>) -> Vec<PathBuf> {
+	let mut outputs: HashMap<PathBuf, PathBuf> = HashMap::new();
+	files.into_iter().filter(|fpath| {
+		let problem = if inside_site(fpath) {
+			let output = output_path(output_dir, fpath);
+			outputs.get(&output).map(|other| Error::SameOutput {
+				output: output.to_owned(),
+				other: other.to_owned(),
+			}).or_else(|| {
+				outputs.insert(output, fpath.to_owned());
+				None
+			})
+		} else {
+			Some(Error::OutsideSite)
+		};
+		problem.map(|problem| errors.push(Error::File {
+			path: fpath.to_owned(),
+			source: Box::new(problem),
+		})).is_none()
+	}).collect()
+}
+
+/// Whether an input file is inside the site's directory. Files outside of it, like `../notes/a.md` or absolute paths, can't be built, as their output would have nowhere to go.
+#[must_use]
+pub fn inside_site(input: &Path) -> bool {
+	input.components().all(|component| matches!(component, Component::CurDir | Component::Normal(_)))
+}
+
+// An input file's path relative to the site's directory, without any leading `./`. Only files inside of it can be built (see `inside_site`).
+pub(crate) fn relative_path(input: &Path) -> PathBuf {
+	input.components().filter_map(|component| {
+		match component {
+			Component::Normal(part) => Some(part),
+			_ => None,
+		}
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn panicking_hooks_fail_the_build() {
+		let child = thread::spawn(|| -> Result<()> { panic!("plugin exploded") });
+		match join("postinit", child) {
+			Err(Error::HookPanicked { hook, message }) => {
+				assert_eq!(hook, "postinit");
+				assert_eq!(message, "plugin exploded");
+			},
+			other => panic!("expected a panic to be reported, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn finished_hooks_keep_their_result() {
+		assert!(join("asyncinit", thread::spawn(|| Ok(()))).is_ok());
+		let child = thread::spawn(|| Err(Error::Math(vec!["line 1: bad".to_string()])));
+		assert!(matches!(join("asyncinit", child), Err(Error::Math(_))));
+	}
+
+	#[test]
+	fn only_inputs_inside_the_site_are_built() {
+		assert!(inside_site(Path::new("./a.md")));
+		assert!(inside_site(Path::new("docs/guide/a.md")));
+		assert!(!inside_site(Path::new("../notes/a.md")));
+		assert!(!inside_site(Path::new("docs/../../a.md")));
+		assert!(!inside_site(Path::new("/home/user/site/a.md")));
+		assert_eq!(output_path(Path::new("out"), Path::new("./docs/a.md")), Path::new("out/docs/a.html"));
+	}
+
+	#[test]
+	fn files_that_would_overwrite_others_are_left_out() {
+		let mut errors = Vec::new();
+		let files = ["./a.md", "../notes/a.md", "a.markdown", "docs/a.md"].iter().map(PathBuf::from).collect();
+		assert_eq!(buildable(files, Path::new("out"), &mut errors), [PathBuf::from("./a.md"), PathBuf::from("docs/a.md")]);
+		let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+		assert_eq!(messages, [
+			"../notes/a.md: Unable to build a file outside of the site's directory! Input globs must only match files inside of it, like \"./**/*.md\".",
+			"a.markdown: Unable to build to \"out/a.html\"! \"./a.md\" is already built there.",
+		]);
+	}
+}
@@ -0,0 +1,217 @@
+//! Header ids, self-links, and the table of contents built from them.
+//!
+//! Ids are added after rendering rather than by the Markdown parser, so that they're the same (and unique) with either parser. They follow GitHub's style: lowercase text with punctuation removed and spaces replaced with dashes, with `-1`, `-2` and so on added to repeated ids.
+
+use crate::{config::Markdown, highlight::unescape, linkcheck::attributes};
+use serde_derive::Serialize;
+use std::collections::HashSet;
+
+/// Starts the comment that holds a page's table of contents, which is added to the end of its HTML for katsite-essentials. The table of contents is written as TOML, in a `toc` array.
+pub const COMMENT_START: &str = "<!--toc\n";
+
+/// A header, along with the headers nested under it.
+#[derive(Serialize, Debug)]
+pub struct Heading {
+	/// From 1 for `<h1>` to 6 for `<h6>`.
+	pub level: u8,
+	/// The header's plain text, without any markup. It's escaped for HTML, as is the id.
+	pub text: String,
+	pub id: String,
+	pub children: Vec<Self>,
+}
+
+#[derive(Serialize)]
+struct Toc<'a> {
+	toc: &'a [Heading],
+}
+
+// The plain text of a header's contents. Math is replaced by its TeX source.
+fn plain_text(html: &str) -> String {
+	let mut text = String::new();
+	let mut rest = html;
+	while let Some(start) = rest.find('<') {
+		text.push_str(&rest[..start]);
+		rest = &rest[start..];
+		if rest.starts_with("<math") {
+			let tex = rest.find("<annotation encoding=\"application/x-tex\">").and_then(|annotation| {
+				let annotation = &rest[annotation..];
+				let start = annotation.find('>')? + 1;
+				Some(&annotation[start..annotation.find("</annotation>")?])
+			});
+			text.push_str(tex.unwrap_or(""));
+			rest = rest.find("</math>").map_or("", |end| &rest[end + "</math>".len()..]);
+		} else {
+			rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+		}
+	}
+	text.push_str(rest);
+	unescape(text.trim())
+}
+
+pub(crate) fn slug(text: &str) -> String {
+	text.to_lowercase()
+		.chars()
+		.filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-' || *c == '_')
+		.map(|c| if c == ' ' { '-' } else { c })
+		.collect()
+}
+
+fn unique_id(base: &str, used: &mut HashSet<String>) -> String {
+	let mut id = base.to_owned();
+	let mut count = 0;
+	while used.contains(&id) {
+		count += 1;
+		id = format!("{}-{}", base, count);
+	}
+	used.insert(id.to_owned());
+	id
+}
+
+fn escape_attribute(input: &str) -> String {
+	input.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Adds a header to the deepest list it belongs in.
+fn insert(toc: &mut Vec<Heading>, heading: Heading) {
+	match toc.last_mut() {
+		Some(parent) if parent.level < heading.level => insert(&mut parent.children, heading),
+		_ => toc.push(heading),
+	}
+}
+
+// Parses an opening header tag like `<h2>` or `<h2 id="...">`, returning its level, any existing (unescaped) id, and its length.
+fn opening_tag(html: &str) -> Option<(u8, Option<String>, usize)> {
+	let bytes = html.as_bytes();
+	let level = match bytes.get(2) {
+		Some(digit @ b'1'..=b'6') => digit - b'0',
+		_ => return None,
+	};
+	if !bytes.get(3).is_some_and(|byte| *byte == b'>' || byte.is_ascii_whitespace()) {
+		return None
+	}
+	let (attributes, length) = attributes(html, 3);
+	if length > html.len() {
+		return None
+	}
+	let id = attributes.into_iter().find(|(name, _)| name == "id").map(|(_, id)| id);
+	Some((level, id, length))
+}
+
+/// Gives every header in rendered HTML an id (unless it already has one) and optionally a self-link, returning the new HTML and its table of contents.
+#[must_use]
+pub fn process_headings(html: &str, options: &Markdown) -> (String, Vec<Heading>) {
+	let mut output = String::with_capacity(html.len());
+	let mut toc = Vec::new();
+	let mut used = HashSet::new();
+	let mut rest = html;
+	while let Some(start) = rest.find("<h") {
+		output.push_str(&rest[..start]);
+		rest = &rest[start..];
+		let (level, existing, length) = if let Some(tag) = opening_tag(rest) {
+			tag
+		} else {
+			output.push_str("<h");
+			rest = &rest[2..];
+			continue
+		};
+		let closing = format!("</h{}>", level);
+		let end = if let Some(end) = rest.find(&closing) {
+			end
+		} else {
+			break
+		};
+
+		let contents = &rest[length..end];
+		let text = plain_text(contents);
+		let has_id = existing.is_some();
+		let id = if let Some(existing) = existing {
+			used.insert(existing.to_owned());
+			existing
+		} else {
+			unique_id(&[options.header_id_prefix.as_str(), &slug(&text)].concat(), &mut used)
+		};
+		if has_id {
+			output.push_str(&rest[..length]);
+		} else {
+			// The id is added to the existing tag, keeping any other attributes that raw HTML headers have.
+			output.push_str(&[&rest[..3], " id=\"", &escape_attribute(&id), "\"", &rest[3..length]].concat());
+		}
+		if options.heading_anchors {
+			output.push_str(&["<a class=\"anchor\" href=\"#", &escape_attribute(&id), "\" aria-hidden=\"true\"></a>"].concat());
+		}
+		output.push_str(contents);
+		output.push_str(&closing);
+		rest = &rest[end + closing.len()..];
+
+		insert(&mut toc, Heading {
+			level,
+			text: escape_attribute(&text),
+			id: escape_attribute(&id),
+			children: Vec::new(),
+		});
+	}
+	output.push_str(rest);
+	(output, toc)
+}
+
+/// The comment that passes a table of contents to katsite-essentials.
+#[must_use]
+pub fn comment(toc: &[Heading]) -> String {
+	// As the text is escaped, it can't end the comment early.
+	let toml = toml::to_string(&Toc {
+		toc,
+	}).unwrap_or_default();
+	[COMMENT_START, &toml, "-->\n"].concat()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ids_are_added_to_the_existing_tag() {
+		let (html, toc) = process_headings("<h2 class=\"note\" style=\"color: red\">Getting started</h2>", &Markdown::default());
+		assert_eq!(html, "<h2 id=\"getting-started\" class=\"note\" style=\"color: red\">Getting started</h2>");
+		assert_eq!(toc[0].id, "getting-started");
+
+		let (html, _) = process_headings("<h1>Title</h1>", &Markdown::default());
+		assert_eq!(html, "<h1 id=\"title\">Title</h1>");
+	}
+
+	#[test]
+	fn existing_ids_are_kept() {
+		let (html, toc) = process_headings("<h2 class=\"note\" id=\"custom\">Getting started</h2>", &Markdown::default());
+		assert_eq!(html, "<h2 class=\"note\" id=\"custom\">Getting started</h2>");
+		assert_eq!(toc[0].id, "custom");
+
+		let (html, _) = process_headings("<h2 id='single'>Quoted</h2>", &Markdown::default());
+		assert_eq!(html, "<h2 id='single'>Quoted</h2>");
+	}
+
+	#[test]
+	fn attributes_ending_in_id_are_not_ids() {
+		let (html, toc) = process_headings("<h2 data-id=\"x\" aria-describedby=\"y\">Getting started</h2>", &Markdown::default());
+		assert_eq!(html, "<h2 id=\"getting-started\" data-id=\"x\" aria-describedby=\"y\">Getting started</h2>");
+		assert_eq!(toc[0].id, "getting-started");
+
+		let (html, toc) = process_headings("<h3 data-id=\"x\" id=\"real\">Text</h3>", &Markdown::default());
+		assert_eq!(html, "<h3 data-id=\"x\" id=\"real\">Text</h3>");
+		assert_eq!(toc[0].id, "real");
+	}
+
+	#[test]
+	fn unquoted_uppercase_and_spaced_ids_are_found() {
+		for (input, id) in &[
+			("<h2 id=intro>Text</h2>", "intro"),
+			("<h2 ID=\"upper\">Text</h2>", "upper"),
+			("<h2 id = \"spaced\">Text</h2>", "spaced"),
+			("<h2\n\tclass=note\n\tId='lines'>Text</h2>", "lines"),
+			("<h2 title=\"a > b\" id=\"after\">Text</h2>", "after"),
+			("<h2 id=\"a&amp;b\">Text</h2>", "a&amp;b"),
+		] {
+			let (html, toc) = process_headings(input, &Markdown::default());
+			assert_eq!(html, *input);
+			assert_eq!(toc[0].id, *id);
+		}
+	}
+}
@@ -0,0 +1,305 @@
+//! Sandboxed WebAssembly plugins, which are WASI command modules in `plugins/<name>.wasm`.
+//!
+//! They follow the same contract as plugin programs: the hook (and the input file, for per-file hooks) is passed as arguments, input is written to stdin, and output is read from stdout. The only files they can access are those in the directory that the input glob searches (read-only), the output directory, and a copy of the config file at `/katsite/config.toml`, which `KATSITE_CONFIG` points to. Directories holding the site's own directory, its config file or its plugins are never made accessible, so if the input files or output directory are in the site's directory itself, like they are by default, plugins can only use stdin and stdout. They're stopped once they run out of fuel or time.
+
+use crate::{config::{Config, CONFIG_FILE}, error::{Error, Result}, external::{timed_out, STDERR_LIMIT}, plugins::{wasm_path, Manifest, PluginEnv, CONFIG_ENV, PLUGIN_DIR, VERBOSITY_ENV}, site::Verbosity};
+use std::{convert::TryFrom, env, fs, thread, io::{self, Write}, path::{Component, Path, PathBuf}, process, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}, time::Duration};
+use wasmtime::{Engine, InstancePre, Linker, Module, Store, Trap};
+use wasmtime_wasi::{pipe::{MemoryInputPipe, MemoryOutputPipe}, preview1::{self, WasiP1Ctx}, DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
+
+// How often the clock used for timeouts ticks.
+const TICK: Duration = Duration::from_millis(10);
+
+// The most a plugin can write to stdout or stderr for a single file or hook.
+const OUTPUT_LIMIT: usize = 256 * 1024 * 1024;
+
+// Where the copy of the config file is inside of the sandbox.
+const CONFIG_DIR: &str = "/katsite";
+const CONFIG_NAME: &str = "config.toml";
+
+// Tells apart the config copies of modules loaded at the same time.
+static CONFIG_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+// A directory that plugins can access.
+struct Preopen {
+	path: PathBuf,
+	guest_path: String,
+	writable: bool,
+}
+
+impl Preopen {
+	// A directory at the same path as outside of the sandbox.
+	fn new(path: PathBuf, writable: bool) -> Self {
+		let guest_path = path.strip_prefix(".").unwrap_or(&path).to_string_lossy().to_string();
+		Self {
+			guest_path: if guest_path.is_empty() { ".".to_string() } else { guest_path },
+			path,
+			writable,
+		}
+	}
+}
+
+// The files that plugins mustn't be able to reach, as they could use them to escape the sandbox, like by installing a native plugin.
+struct SiteFiles {
+	root: Option<PathBuf>,
+	config: Option<PathBuf>,
+	plugins: Option<PathBuf>,
+}
+
+impl SiteFiles {
+	fn new(root: &Path, config: &Path, plugins: &Path) -> Self {
+		Self {
+			root: root.canonicalize().ok(),
+			config: config.canonicalize().ok(),
+			plugins: plugins.canonicalize().ok(),
+		}
+	}
+
+	// Whether a directory can be made accessible, which it can't if it holds any of the site's files, or is inside of its plugins directory.
+	fn exposable(&self, dir: &Path) -> bool {
+		let dir = match dir.canonicalize() {
+			Ok(dir) => dir,
+			Err(_) => return false,
+		};
+		let holds = |path: &Option<PathBuf>| path.as_ref().is_some_and(|path| path.starts_with(&dir));
+		!holds(&self.root) && !holds(&self.config) && !holds(&self.plugins) && !self.plugins.as_ref().is_some_and(|plugins| dir.starts_with(plugins))
+	}
+}
+
+// The directory that input files are searched for in, which is the part of the input glob before any wildcards.
+fn glob_root(pattern: &str) -> PathBuf {
+	let components: Vec<Component> = Path::new(pattern).components().collect();
+	let mut root = PathBuf::new();
+	for component in &components[..components.len().saturating_sub(1)] {
+		if component.as_os_str().to_string_lossy().contains(&['*', '?', '['][..]) {
+			break
+		}
+		root.push(component);
+	}
+	if root.as_os_str().is_empty() {
+		root.push(".");
+	}
+	root
+}
+
+fn wasm_error(plugin: &str) -> impl FnOnce(wasmtime::Error) -> Error + '_ {
+	move |err| Error::Wasm {
+		plugin: plugin.to_string(),
+		message: format!("{:#}", err),
+	}
+}
+
+/// A compiled WebAssembly plugin, which is instantiated in a new sandbox for every hook it handles.
+pub struct WasmModule {
+	engine: Engine,
+	instance: InstancePre<WasiP1Ctx>,
+	preopens: Vec<Preopen>,
+	site_files: SiteFiles,
+	// The directory holding the copy of the config file, which is removed along with the module.
+	config_dir: PathBuf,
+	// Stops the thread that ticks the engine's clock.
+	running: Arc<AtomicBool>,
+}
+
+impl WasmModule {
+	/// Compiles a plugin's module, and works out which directories it can access.
+	pub fn load(plugin: &str, config: &Config, manifest: &Manifest) -> Result<Self> {
+		if manifest.persistent {
+			return Err(Error::Manifest {
+				plugin: plugin.to_string(),
+				message: "WebAssembly plugins can't be persistent".to_string(),
+			})
+		}
+		Self::compile(plugin, config, |engine| Module::from_file(engine, wasm_path(plugin)))
+	}
+
+	fn compile<F: FnOnce(&Engine) -> wasmtime::Result<Module>>(plugin: &str, config: &Config, module: F) -> Result<Self> {
+		let mut engine_config = wasmtime::Config::new();
+		engine_config.consume_fuel(true)
+			.epoch_interruption(true);
+		let engine = Engine::new(&engine_config).map_err(wasm_error(plugin))?;
+		let module = module(&engine).map_err(wasm_error(plugin))?;
+		if module.get_export("_start").is_none() {
+			return Err(Error::Wasm {
+				plugin: plugin.to_string(),
+				message: "The module doesn't export a _start function. Plugins must be built as WASI commands.".to_string(),
+			})
+		}
+		let mut linker = Linker::new(&engine);
+		preview1::add_to_linker_sync(&mut linker, |ctx| ctx).map_err(wasm_error(plugin))?;
+		let instance = linker.instantiate_pre(&module).map_err(wasm_error(plugin))?;
+
+		let input = glob_root(&config.files.input_glob);
+		let output = &config.files.output_dir;
+		let mut preopens = vec![Preopen::new(output.to_owned(), true)];
+		if input != *output {
+			preopens.push(Preopen::new(input, false));
+		}
+
+		// Plugins get a copy of the config file, as the site's directory that it's usually in can't be made accessible.
+		let config_dir = env::temp_dir().join(format!("katsite-{}-{}-{}", process::id(), CONFIG_COPIES.fetch_add(1, Ordering::Relaxed), plugin));
+		fs::create_dir_all(&config_dir).map_err(Error::io(&config_dir))?;
+		let config_copy = config_dir.join(CONFIG_NAME);
+		fs::write(&config_copy, config.raw.to_string()).map_err(Error::io(&config_copy))?;
+		preopens.push(Preopen {
+			path: config_dir.to_owned(),
+			guest_path: CONFIG_DIR.to_string(),
+			writable: false,
+		});
+		let site_files = SiteFiles::new(Path::new("."), config.source().unwrap_or_else(|| Path::new(CONFIG_FILE)), Path::new(PLUGIN_DIR));
+
+		let running = Arc::new(AtomicBool::new(true));
+		{
+			let engine = engine.clone();
+			let running = Arc::clone(&running);
+			thread::spawn(move || {
+				while running.load(Ordering::Relaxed) {
+					thread::sleep(TICK);
+					engine.increment_epoch();
+				}
+			});
+		}
+
+		Ok(Self {
+			engine,
+			instance,
+			preopens,
+			site_files,
+			config_dir,
+			running,
+		})
+	}
+
+	/// Runs the plugin's _start function in a new sandbox. If there's any input, it's written to the plugin's stdin and the plugin's stdout is returned.
+	pub fn run(&self, plugin: &str, hook: &str, args: &[&str], input: Option<Vec<u8>>, env: &PluginEnv) -> Result<Vec<u8>> {
+		let stdout = MemoryOutputPipe::new(OUTPUT_LIMIT);
+		let stderr = MemoryOutputPipe::new(OUTPUT_LIMIT);
+		let mut wasi = WasiCtxBuilder::new();
+		wasi.arg(plugin)
+			.args(args)
+			.env(CONFIG_ENV, [CONFIG_DIR, "/", CONFIG_NAME].concat())
+			.env(VERBOSITY_ENV, env.verbosity.as_str())
+			.stderr(stderr.clone());
+		if let Some(input) = input {
+			wasi.stdin(MemoryInputPipe::new(input))
+				.stdout(stdout.clone());
+		} else if env.verbosity != Verbosity::Quiet {
+			wasi.inherit_stdout();
+		}
+		// The output directory may not have been created yet, during the preinit hook.
+		for preopen in self.preopens.iter().filter(|preopen| preopen.path.is_dir() && (preopen.path == self.config_dir || self.site_files.exposable(&preopen.path))) {
+			let (dir_perms, file_perms) = if preopen.writable {
+				(DirPerms::all(), FilePerms::all())
+			} else {
+				(DirPerms::READ, FilePerms::READ)
+			};
+			wasi.preopened_dir(&preopen.path, &preopen.guest_path, dir_perms, file_perms)
+				.map_err(|err| Error::io(&preopen.path)(io::Error::other(err.to_string())))?;
+		}
+
+		let timeout = env.settings.timeout(plugin);
+		let mut store = Store::new(&self.engine, wasi.build_p1());
+		store.set_fuel(env.settings.fuel(plugin).unwrap_or(u64::MAX)).map_err(wasm_error(plugin))?;
+		store.set_epoch_deadline(timeout.map_or(u64::MAX, |timeout| {
+			u64::try_from(timeout.as_millis() / TICK.as_millis()).unwrap_or(u64::MAX).saturating_add(1)
+		}));
+		let result = self.instance.instantiate(&mut store).and_then(|instance| {
+			instance.get_typed_func::<(), ()>(&mut store, "_start")?.call(&mut store, ())
+		});
+		drop(store);
+
+		// Error output is only forwarded once the plugin has finished, as the sandbox buffers it.
+		let errors = stderr.contents();
+		let _ = io::stderr().write_all(&errors);
+		let reason = match result {
+			Ok(()) => None,
+			Err(err) => match (err.downcast_ref::<I32Exit>(), err.downcast_ref::<Trap>()) {
+				(Some(I32Exit(0)), _) => None,
+				(Some(I32Exit(code)), _) => Some(format!("exited with status {}", code)),
+				(None, Some(Trap::OutOfFuel)) => Some("ran out of fuel".to_string()),
+				(None, Some(Trap::Interrupt)) => Some(timed_out(timeout.unwrap_or_default())),
+				_ => Some(format!("crashed ({})", err.root_cause())),
+			},
+		};
+		if let Some(reason) = reason {
+			let tail = &errors[errors.len().saturating_sub(STDERR_LIMIT)..];
+			return Err(Error::PluginFailed {
+				plugin: plugin.to_string(),
+				hook: hook.to_string(),
+				reason,
+				stderr: String::from_utf8_lossy(tail).to_string(),
+			})
+		}
+		Ok(stdout.contents().to_vec())
+	}
+}
+
+impl Drop for WasmModule {
+	fn drop(&mut self) {
+		self.running.store(false, Ordering::Relaxed);
+		let _ = fs::remove_dir_all(&self.config_dir);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Opens `lib.rs` from the first preopen and the config copy from the second, then exits with 1 if any of them can open the site's config file or plugins.
+	const GUEST: &str = r#"(module
+		(import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+		(import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+		(memory (export "memory") 1)
+		(data (i32.const 16) "lib.rs")
+		(data (i32.const 32) "config.toml")
+		(data (i32.const 48) "conf.toml")
+		(data (i32.const 64) "../conf.toml")
+		(data (i32.const 80) "plugins")
+		(data (i32.const 96) "../plugins")
+		(func $open (param $fd i32) (param $path i32) (param $length i32) (result i32)
+			(call $path_open (local.get $fd) (i32.const 0) (local.get $path) (local.get $length) (i32.const 0) (i64.const 2) (i64.const 2) (i32.const 0) (i32.const 8)))
+		(func (export "_start") (local $fd i32)
+			(if (call $open (i32.const 3) (i32.const 16) (i32.const 6)) (then (call $proc_exit (i32.const 2))))
+			(if (call $open (i32.const 4) (i32.const 32) (i32.const 11)) (then (call $proc_exit (i32.const 3))))
+			(local.set $fd (i32.const 3))
+			(loop $next
+				(if (i32.eqz (call $open (local.get $fd) (i32.const 48) (i32.const 9))) (then (call $proc_exit (i32.const 1))))
+				(if (i32.eqz (call $open (local.get $fd) (i32.const 64) (i32.const 12))) (then (call $proc_exit (i32.const 1))))
+				(if (i32.eqz (call $open (local.get $fd) (i32.const 80) (i32.const 7))) (then (call $proc_exit (i32.const 1))))
+				(if (i32.eqz (call $open (local.get $fd) (i32.const 96) (i32.const 10))) (then (call $proc_exit (i32.const 1))))
+				(local.set $fd (i32.add (local.get $fd) (i32.const 1)))
+				(br_if $next (i32.lt_u (local.get $fd) (i32.const 8))))))"#;
+
+	fn temp_site(name: &str) -> PathBuf {
+		let site = env::temp_dir().join(format!("katsite-test-{}-{}", process::id(), name));
+		for dir in &["plugins", "posts", "output"] {
+			fs::create_dir_all(site.join(dir)).unwrap();
+		}
+		fs::write(site.join(CONFIG_FILE), "").unwrap();
+		site
+	}
+
+	#[test]
+	fn directories_holding_site_files_are_not_exposed() {
+		let site = temp_site("exposable");
+		let files = SiteFiles::new(&site, &site.join(CONFIG_FILE), &site.join("plugins"));
+		assert!(files.exposable(&site.join("posts")));
+		assert!(files.exposable(&site.join("output")));
+		assert!(!files.exposable(&site));
+		assert!(!files.exposable(&site.join("posts/..")));
+		assert!(!files.exposable(&site.join("plugins")));
+		assert!(!files.exposable(&site.join("plugins/../plugins")));
+		assert!(!files.exposable(site.parent().unwrap()));
+		assert!(!files.exposable(&site.join("missing")));
+		fs::remove_dir_all(site).unwrap();
+	}
+
+	#[test]
+	fn plugins_cannot_open_the_config_file_or_plugins() {
+		// The site's directory is the crate's, so the output directory, like by default, isn't exposed at all.
+		let config = Config::from_toml("plugins_list = []\n[files]\ninput_glob = \"./src/*.rs\"\noutput_dir = \".\"\n").unwrap();
+		let module = WasmModule::compile("sandbox", &config, |engine| Module::new(engine, GUEST)).unwrap();
+		let env = PluginEnv::new(PathBuf::from(CONFIG_FILE), &config, Verbosity::Quiet, Vec::new());
+		module.run("sandbox", "postinit", &["postinit"], None, &env).unwrap();
+	}
+}
@@ -0,0 +1,180 @@
+use crate::{config::Config, error::{Error, Result}, shortcodes::SHORTCODE_DIR, site::{output_path, Site, Stages}};
+use glob::Pattern;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::{env, fs, path::{Path, PathBuf}, sync::mpsc::{channel, Receiver}, time::Duration};
+
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+enum Change {
+	Config,
+	Markdown,
+	Shortcode,
+	Template,
+	Asset,
+}
+
+fn classify(site: &Site, pattern: &Pattern, root: &Path, path: &Path) -> Option<Change> {
+	let relative = path.strip_prefix(root).ok()?;
+
+	let ignored = relative.components().any(|component| {
+		let component = component.as_os_str().to_string_lossy();
+		component.starts_with('.') || component == "target"
+	});
+	let file_name = relative.file_name()?.to_string_lossy();
+	if ignored || file_name.ends_with('~') {
+		return None
+	}
+
+	let config_path = root.join(site.config_path()).canonicalize().ok();
+	if config_path.as_deref() == Some(path) || relative.starts_with("plugins") {
+		return Some(Change::Config)
+	}
+	if pattern.matches_path(relative) || pattern.matches_path(&Path::new(".").join(relative)) {
+		return Some(Change::Markdown)
+	}
+
+	let output_dir = root.join(&site.config().files.output_dir).canonicalize().ok()?;
+	if output_dir != root && path.starts_with(&output_dir) {
+		return None
+	}
+
+	if relative.starts_with(SHORTCODE_DIR) {
+		return Some(Change::Shortcode)
+	}
+	if relative.extension().is_some_and(|extension| extension == "liquid") {
+		Some(Change::Template)
+	} else {
+		Some(Change::Asset)
+	}
+}
+
+fn event_paths(event: DebouncedEvent) -> Vec<PathBuf> {
+	match event {
+		DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) => vec![path],
+		DebouncedEvent::Rename(from, to) => vec![from, to],
+		_ => Vec::new(),
+	}
+}
+
+fn collect_changes(rx: &Receiver<DebouncedEvent>) -> Option<Vec<PathBuf>> {
+	let mut paths = event_paths(rx.recv().ok()?);
+	while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+		paths.extend(event_paths(event));
+	}
+	paths.sort();
+	paths.dedup();
+	Some(paths)
+}
+
+fn remove_output(config: &Config, root: &Path, path: &Path) {
+	if let Ok(relative) = path.strip_prefix(root) {
+		let output = output_path(&config.files.output_dir, relative);
+		println!("Removing {}...", output.to_string_lossy());
+		let _ = fs::remove_file(&output);
+		let _ = fs::remove_file(output.with_extension("html.br"));
+	}
+}
+
+/// Watches the site's directory, rebuilding whatever is affected by each change. `on_rebuild` is called after every rebuild.
+///
+/// Build errors are reported without stopping the watcher. This only returns if watching fails.
+///
+/// # Errors
+///
+/// Returns [`Error::Watch`] if the file watcher can't be started, or stops unexpectedly.
+pub fn watch(site: &mut Site, on_rebuild: &dyn Fn()) -> Result<()> {
+	let root = env::current_dir().and_then(|dir| dir.canonicalize()).map_err(Error::io("."))?;
+
+	let (tx, rx) = channel();
+	let mut watcher = watcher(tx, DEBOUNCE)?;
+	watcher.watch(&root, RecursiveMode::Recursive)?;
+
+	println!("Watching for changes, press Ctrl+C to stop.");
+	while let Some(paths) = collect_changes(&rx) {
+		let pattern = match Pattern::new(&site.config().files.input_glob) {
+			Ok(pattern) => pattern,
+			Err(err) => {
+				eprintln!("{}", Error::from(err));
+				continue
+			},
+		};
+
+		let mut stages = Stages::none();
+		let mut changed = Vec::new();
+		for path in paths {
+			let change = if let Some(change) = classify(site, &pattern, &root, &path) {
+				change
+			} else {
+				continue
+			};
+			changed.push(path.strip_prefix(&root).unwrap_or(&path).to_string_lossy().to_string());
+
+			match change {
+				Change::Config => stages = Stages::all(),
+				Change::Markdown => {
+					if path.exists() {
+						if let Some(files) = stages.markdown_files.as_mut() {
+							files.push(path.strip_prefix(&root).unwrap_or(&path).to_path_buf());
+						}
+					} else {
+						remove_output(site.config(), &root, &path);
+					}
+					// Any page could link to it. Other pages are only rebuilt if one was added, removed or retitled, as that changes the build key.
+					if site.config().markdown.wiki_links {
+						stages.markdown_files = None;
+					}
+					stages.markdown = true;
+					stages.postinit = true;
+				},
+				// Any page could use the shortcode.
+				Change::Shortcode => {
+					stages.markdown = true;
+					stages.markdown_files = None;
+					stages.postinit = true;
+				},
+				Change::Template => stages.postinit = true,
+				// Plugins may generate input files from assets, such as data files.
+				Change::Asset => {
+					stages.preinit = true;
+					stages.asyncinit = true;
+				},
+			}
+		}
+
+		if changed.is_empty() {
+			continue
+		}
+
+		println!("\nChanged: {}", changed.join(", "));
+		if stages.markdown_files.is_none() {
+			if let Err(err) = site.reload_config() {
+				eprintln!("{}", err);
+				continue
+			}
+		}
+
+		match site.build_stages(&stages) {
+			Ok(report) => {
+				let mut summary = Vec::new();
+				if stages.markdown {
+					summary.push(format!("{} page(s) converted", report.converted.len()));
+				}
+				if stages.asyncinit {
+					summary.push("assets rebuilt".to_string());
+				}
+				if stages.postinit {
+					summary.push("templates rendered".to_string());
+				}
+				println!("Rebuilt in {:.2}s: {}.", report.duration.as_secs_f64(), summary.join(", "));
+				on_rebuild();
+			},
+			Err(err) => eprintln!("Rebuild failed! {}", err),
+		}
+
+		if site.config().files.output_dir.canonicalize().ok().as_ref() == Some(&root) {
+			while rx.recv_timeout(DEBOUNCE * 2).is_ok() {}
+		}
+	}
+
+	Err(Error::Watch(notify::Error::Generic("File watcher stopped unexpectedly!".to_string())))
+}